@@ -1,6 +1,6 @@
 with import <nixpkgs> {};
 stdenv.mkDerivation {
     name = "dev-environment"; # Probably put a more meaningful name here
-    buildInputs = [ pkg-config libxkbcommon ];
+    buildInputs = [ pkg-config libxkbcommon udev ];
 }
 
@@ -0,0 +1,208 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+use anyhow::{anyhow, bail, Result};
+use cpal::traits::*;
+
+/// Device and buffer size of the audio output, see `uw8 run --audio-device`.
+#[derive(Clone, Default)]
+pub struct AudioConfig {
+    /// case-insensitive part of the device name, the default device if `None`
+    pub device: Option<String>,
+    /// buffer size in frames, 256 by default
+    pub buffer_size: Option<u32>,
+}
+
+/// Names of all output devices of the default host, the default device marked with `*`.
+pub fn list_audio_devices() -> Result<Vec<String>> {
+    let host = cpal::default_host();
+    let default_name = host.default_output_device().and_then(|d| d.name().ok());
+    let mut names = vec![];
+    for device in host.output_devices()? {
+        let name = device.name()?;
+        if Some(&name) == default_name.as_ref() {
+            names.push(format!("* {}", name));
+        } else {
+            names.push(format!("  {}", name));
+        }
+    }
+    Ok(names)
+}
+
+/// Opens the configured output device with the config closest to stereo f32 at 44100Hz.
+///
+/// Any sample format, channel count and rate is accepted, the samples generated by the
+/// sound instance are resampled and then converted by `write_output`.
+pub(crate) fn open_output(
+    audio_config: &AudioConfig,
+) -> Result<(cpal::Device, cpal::StreamConfig, cpal::SampleFormat)> {
+    let host = cpal::default_host();
+    let device = match audio_config.device {
+        Some(ref name) => {
+            let pattern = name.to_lowercase();
+            host.output_devices()?
+                .find(|device| {
+                    device
+                        .name()
+                        .map(|n| n.to_lowercase().contains(&pattern))
+                        .unwrap_or(false)
+                })
+                .ok_or_else(|| anyhow!("No audio output device matching '{}'", name))?
+        }
+        None => host
+            .default_output_device()
+            .ok_or_else(|| anyhow!("No audio output device available"))?,
+    };
+
+    let mut configs: Vec<_> = device.supported_output_configs()?.collect();
+    configs.sort_by_key(|config| {
+        let channels = match config.channels() {
+            2 => 0,
+            1 => 2,
+            _ => 1,
+        };
+        let format = match config.sample_format() {
+            cpal::SampleFormat::F32 => 0,
+            cpal::SampleFormat::I16 => 1,
+            cpal::SampleFormat::U16 => 2,
+        };
+        let rate = 44100
+            .max(config.min_sample_rate().0)
+            .min(config.max_sample_rate().0);
+        let rate = if rate >= 44100 {
+            rate - 44100
+        } else {
+            (44100 - rate) * 1000
+        };
+        (channels, format, rate)
+    });
+    let config = configs
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("Could not find an output config"))?;
+    let sample_rate = cpal::SampleRate(44100)
+        .max(config.min_sample_rate())
+        .min(config.max_sample_rate());
+    let config = config.with_sample_rate(sample_rate);
+    let requested_size = audio_config.buffer_size.unwrap_or(256);
+    let buffer_size = match *config.buffer_size() {
+        cpal::SupportedBufferSize::Unknown if audio_config.buffer_size.is_some() => {
+            cpal::BufferSize::Fixed(requested_size)
+        }
+        cpal::SupportedBufferSize::Unknown => cpal::BufferSize::Default,
+        cpal::SupportedBufferSize::Range { min, max } => {
+            cpal::BufferSize::Fixed(requested_size.max(min).min(max))
+        }
+    };
+    let sample_format = config.sample_format();
+    let stream_config = cpal::StreamConfig {
+        buffer_size,
+        ..config.config()
+    };
+
+    Ok((device, stream_config, sample_format))
+}
+
+/// Converts interleaved stereo samples to the format and channel count of the output.
+/// Mono outputs get the average of both channels, additional channels are silent.
+pub(crate) fn write_output(output: &mut cpal::Data, stereo: &[f32], channels: usize) {
+    fn write<T: cpal::Sample>(output: &mut [T], stereo: &[f32], channels: usize) {
+        for (frame, sample) in output.chunks_mut(channels).zip(stereo.chunks(2)) {
+            if channels == 1 {
+                frame[0] = T::from(&((sample[0] + sample[1]) * 0.5));
+            } else {
+                frame[0] = T::from(&sample[0]);
+                frame[1] = T::from(&sample[1]);
+                for v in &mut frame[2..] {
+                    *v = T::from(&0.0f32);
+                }
+            }
+        }
+    }
+
+    match output.sample_format() {
+        cpal::SampleFormat::F32 => write(output.as_slice_mut::<f32>().unwrap(), stereo, channels),
+        cpal::SampleFormat::I16 => write(output.as_slice_mut::<i16>().unwrap(), stereo, channels),
+        cpal::SampleFormat::U16 => write(output.as_slice_mut::<u16>().unwrap(), stereo, channels),
+    }
+}
+
+/// Writes the stereo samples played by the audio callback to a WAV file, at the sample rate
+/// of the output device. The file is written on its own thread, the callback only hands over
+/// copies of its buffers and never waits for the disk.
+pub(crate) struct AudioRecorder {
+    path: PathBuf,
+    sample_rate: u32,
+    tx: Option<mpsc::SyncSender<Vec<f32>>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl AudioRecorder {
+    pub(crate) fn new(path: &Path) -> AudioRecorder {
+        AudioRecorder {
+            path: path.to_path_buf(),
+            sample_rate: 0,
+            tx: None,
+            thread: None,
+        }
+    }
+
+    /// Returns the sender for the buffers of a new audio stream. The file is created for
+    /// the first stream, later streams (eg. after reloading the cart) continue the recording.
+    pub(crate) fn sender(&mut self, sample_rate: u32) -> Result<mpsc::SyncSender<Vec<f32>>> {
+        if let Some(ref tx) = self.tx {
+            if sample_rate != self.sample_rate {
+                bail!(
+                    "Sample rate changed from {}Hz to {}Hz during audio recording",
+                    self.sample_rate,
+                    sample_rate
+                );
+            }
+            return Ok(tx.clone());
+        }
+
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(&self.path, spec)?;
+        let (tx, rx) = mpsc::sync_channel::<Vec<f32>>(256);
+        self.thread = Some(thread::spawn(move || {
+            let mut unflushed = 0;
+            'buffers: for buffer in rx {
+                for &sample in &buffer {
+                    if let Err(err) = writer.write_sample(sample) {
+                        eprintln!("Failed to write audio recording: {}", err);
+                        break 'buffers;
+                    }
+                }
+                // update the header once per second, so that the file stays readable
+                // even if the process is killed
+                unflushed += buffer.len();
+                if unflushed >= sample_rate as usize * 2 {
+                    let _ = writer.flush();
+                    unflushed = 0;
+                }
+            }
+            if let Err(err) = writer.finalize() {
+                eprintln!("Failed to finish audio recording: {}", err);
+            }
+        }));
+        self.sample_rate = sample_rate;
+        self.tx = Some(tx.clone());
+        Ok(tx)
+    }
+}
+
+impl Drop for AudioRecorder {
+    fn drop(&mut self) {
+        // the writer finishes the file once all senders are gone
+        self.tx = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+
+pub const SCREEN_WIDTH: u32 = 320;
+pub const SCREEN_HEIGHT: u32 = 240;
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// Resolves the 8bpp framebuffer through the 32bpp palette into 24bpp rgb data,
+/// scaling each pixel up by `scale` in both directions.
+pub fn framebuffer_to_rgb(framebuffer: &[u8], palette: &[u8], scale: u32) -> Vec<u8> {
+    let scale = scale.max(1) as usize;
+    let width = SCREEN_WIDTH as usize;
+    let mut rgb = Vec::with_capacity(framebuffer.len() * scale * scale * 3);
+    for row in framebuffer.chunks_exact(width) {
+        let start = rgb.len();
+        for &color_index in row {
+            let offset = color_index as usize * 4;
+            for _ in 0..scale {
+                rgb.extend_from_slice(&palette[offset..offset + 3]);
+            }
+        }
+        for _ in 1..scale {
+            rgb.extend_from_within(start..start + width * scale * 3);
+        }
+    }
+    rgb
+}
+
+pub fn write_png(path: &Path, framebuffer: &[u8], palette: &[u8], scale: u32) -> Result<()> {
+    let scale = scale.max(1);
+    let rgb = framebuffer_to_rgb(framebuffer, palette, scale);
+
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        SCREEN_WIDTH * scale,
+        SCREEN_HEIGHT * scale,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&rgb)?;
+
+    Ok(())
+}
+
+/// Writes interleaved stereo samples as a 32bit float WAV file.
+pub fn write_wav(path: &Path, samples: &[f32]) -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for &sample in samples {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+/// Writes frames as an uncompressed YUV4MPEG2 stream at 60fps, which can be
+/// read by most video tools, eg. ffmpeg.
+pub struct Y4mWriter {
+    out: BufWriter<File>,
+    scale: u32,
+}
+
+impl Y4mWriter {
+    pub fn create(path: &Path, scale: u32) -> Result<Y4mWriter> {
+        let scale = scale.max(1);
+        let mut out = BufWriter::new(File::create(path)?);
+        // 4:4:4 chroma, so that single pixels keep their color
+        writeln!(
+            out,
+            "YUV4MPEG2 W{} H{} F60:1 Ip A1:1 C444",
+            SCREEN_WIDTH * scale,
+            SCREEN_HEIGHT * scale
+        )?;
+        Ok(Y4mWriter { out, scale })
+    }
+
+    pub fn write_frame(&mut self, framebuffer: &[u8], palette: &[u8]) -> Result<()> {
+        let rgb = framebuffer_to_rgb(framebuffer, palette, self.scale);
+        let num_pixels = rgb.len() / 3;
+        let mut planes = vec![0u8; num_pixels * 3];
+        for (i, pixel) in rgb.chunks_exact(3).enumerate() {
+            let (r, g, b) = (pixel[0] as f32, pixel[1] as f32, pixel[2] as f32);
+            // BT.601, limited range
+            let y = 16. + (65.481 * r + 128.553 * g + 24.966 * b) / 255.;
+            let u = 128. + (-37.797 * r - 74.203 * g + 112. * b) / 255.;
+            let v = 128. + (112. * r - 93.786 * g - 18.214 * b) / 255.;
+            planes[i] = y.round() as u8;
+            planes[num_pixels + i] = u.round() as u8;
+            planes[num_pixels * 2 + i] = v.round() as u8;
+        }
+        self.out.write_all(b"FRAME\n")?;
+        self.out.write_all(&planes)?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
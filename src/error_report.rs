@@ -0,0 +1,129 @@
+use std::fmt::{self, Write};
+
+use anyhow::{anyhow, Error};
+use wasmtime::{Trap, WasmBacktrace};
+
+use crate::capture::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Turns an error returned from calling into the cart into a readable report naming the
+/// entry point, the kind of trap and the wasm backtrace. Function names are taken from
+/// the name section if present, eg. when compiled with `uw8 compile --debug`.
+pub(crate) fn trap_report(err: Error, entry_point: &str) -> Error {
+    // the backtrace is attached as context, so the outermost message is the backtrace itself.
+    // Traps raised by wasm name their kind, errors from host functions are the root cause.
+    let message = match err.downcast_ref::<Trap>() {
+        Some(trap) => trap.to_string(),
+        None => err.root_cause().to_string(),
+    };
+
+    let mut report = match err.downcast_ref::<BudgetExceeded>() {
+        Some(budget) => budget.to_string(),
+        None => format!("{} failed: {}", entry_point, message),
+    };
+    if let Some(backtrace) = err.downcast_ref::<WasmBacktrace>() {
+        report.push_str("\nwasm backtrace:");
+        for (index, frame) in backtrace.frames().iter().enumerate() {
+            let _ = write!(report, "\n  {:>3}: ", index);
+            if let Some(module) = frame.module_name() {
+                let _ = write!(report, "{}!", module);
+            }
+            match frame.func_name() {
+                Some(name) => report.push_str(name),
+                None => {
+                    let _ = write!(report, "func[{}]", frame.func_index());
+                }
+            }
+            if let Some(offset) = frame.func_offset() {
+                let _ = write!(report, " +{:#x}", offset);
+            }
+        }
+    }
+    anyhow!(report)
+}
+
+/// Added as context to the trap of an entry point that ran out of its fuel budget.
+#[derive(Debug)]
+pub(crate) struct BudgetExceeded {
+    pub(crate) entry_point: &'static str,
+    pub(crate) fuel: u64,
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: frame budget exceeded, used more than {} fuel",
+            self.entry_point, self.fuel
+        )
+    }
+}
+
+const BANNER_LINES: usize = 3;
+const WIDTH: usize = SCREEN_WIDTH as usize;
+
+/// The last good frame of a cart that stopped with an error, with the
+/// beginning of the error message drawn in a banner over the top of the screen.
+pub(crate) struct ErrorScreen {
+    pub(crate) framebuffer: Vec<u8>,
+    pub(crate) palette: Vec<u8>,
+}
+
+impl ErrorScreen {
+    /// `screen` is the memory from the framebuffer to the end of the font, 0x78-0x13c00.
+    pub(crate) fn new(screen: &[u8], message: &str) -> ErrorScreen {
+        let mut framebuffer = screen[..WIDTH * SCREEN_HEIGHT as usize].to_vec();
+        let palette = screen[0x13000 - 0x78..0x13400 - 0x78].to_vec();
+        let font = &screen[0x13400 - 0x78..0x13c00 - 0x78];
+
+        let background = nearest_color(&palette, [160, 0, 0]);
+        let text = nearest_color(&palette, [255, 255, 255]);
+
+        let columns = WIDTH / 8 - 1;
+        let mut lines: Vec<String> = vec![];
+        for line in message.lines() {
+            let chars: Vec<char> = line.chars().collect();
+            for chunk in chars.chunks(columns) {
+                lines.push(chunk.iter().collect());
+            }
+        }
+        lines.truncate(BANNER_LINES);
+
+        let height = lines.len() * 8 + 8;
+        framebuffer[..WIDTH * height].fill(background);
+        for (row, line) in lines.iter().enumerate() {
+            for (column, c) in line.chars().enumerate() {
+                let glyph = &font[(c as usize & 255) * 8..][..8];
+                for (y, bits) in glyph.iter().enumerate() {
+                    for x in 0..8 {
+                        if bits & (0x80 >> x) != 0 {
+                            let px = 4 + column * 8 + x;
+                            let py = 4 + row * 8 + y;
+                            framebuffer[px + py * WIDTH] = text;
+                        }
+                    }
+                }
+            }
+        }
+
+        ErrorScreen {
+            framebuffer,
+            palette,
+        }
+    }
+}
+
+fn nearest_color(palette: &[u8], color: [u8; 3]) -> u8 {
+    let distance = |entry: &[u8]| {
+        entry
+            .iter()
+            .zip(&color)
+            .map(|(&a, &b)| (a as i32 - b as i32).pow(2))
+            .sum::<i32>()
+    };
+    palette
+        .chunks(4)
+        .enumerate()
+        .min_by_key(|(_, entry)| distance(&entry[..3]))
+        .map(|(index, _)| index as u8)
+        .unwrap_or(0)
+}
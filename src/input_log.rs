@@ -0,0 +1,73 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+const MAGIC: &[u8; 4] = b"UW8I";
+const VERSION: u8 = 1;
+const RESET_FLAG: u8 = 1;
+
+/// The input of a single frame as stored in an input log.
+#[derive(Clone, Copy, Debug)]
+pub struct InputFrame {
+    pub gamepads: [u8; 4],
+    pub reset: bool,
+}
+
+/// Writes one five byte record per frame: the four gamepad bytes followed by a flags byte.
+///
+/// Each frame is written straight to the file so that the log is complete even if
+/// the runtime exits on a cart error.
+pub struct InputRecorder {
+    file: File,
+}
+
+impl InputRecorder {
+    pub fn create(path: &Path) -> Result<InputRecorder> {
+        let mut file = File::create(path)?;
+        file.write_all(MAGIC)?;
+        file.write_all(&[VERSION])?;
+        Ok(InputRecorder { file })
+    }
+
+    pub fn record(&mut self, frame: InputFrame) -> Result<()> {
+        let mut record = [0u8; 5];
+        record[..4].copy_from_slice(&frame.gamepads);
+        if frame.reset {
+            record[4] |= RESET_FLAG;
+        }
+        self.file.write_all(&record)?;
+        Ok(())
+    }
+}
+
+pub struct InputPlayback {
+    data: Vec<u8>,
+    position: usize,
+}
+
+impl InputPlayback {
+    pub fn open(path: &Path) -> Result<InputPlayback> {
+        let mut data = vec![];
+        File::open(path)?.read_to_end(&mut data)?;
+        if data.len() < 5 || &data[..4] != MAGIC {
+            bail!("Not a MicroW8 input log");
+        }
+        if data[4] != VERSION {
+            bail!("Unsupported input log version {}", data[4]);
+        }
+        Ok(InputPlayback { data, position: 5 })
+    }
+
+    pub fn next_frame(&mut self) -> Option<InputFrame> {
+        let record = self.data.get(self.position..self.position + 5)?;
+        self.position += 5;
+        let mut gamepads = [0u8; 4];
+        gamepads.copy_from_slice(&record[..4]);
+        Some(InputFrame {
+            gamepads,
+            reset: record[4] & RESET_FLAG != 0,
+        })
+    }
+}
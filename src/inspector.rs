@@ -0,0 +1,290 @@
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::ops::Range;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use ansi_term::Colour;
+use anyhow::{anyhow, bail, Error, Result};
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+const BUTTON_NAMES: &[u8; 8] = b"UDLRABXY";
+
+/// A named memory location shown by the [`MemoryInspector`], parsed from
+/// `name=address[:type]`.
+pub struct Watch {
+    name: String,
+    address: usize,
+    ty: WatchType,
+    value: String,
+    last_change: u32,
+}
+
+#[derive(Clone, Copy)]
+enum WatchType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+}
+
+impl WatchType {
+    fn size(self) -> usize {
+        match self {
+            WatchType::U8 | WatchType::I8 => 1,
+            WatchType::U16 | WatchType::I16 => 2,
+            WatchType::U32 | WatchType::I32 | WatchType::F32 => 4,
+        }
+    }
+
+    fn format(self, bytes: &[u8]) -> String {
+        let mut word = [0u8; 4];
+        word[..bytes.len()].copy_from_slice(bytes);
+        let word = u32::from_le_bytes(word);
+        match self {
+            WatchType::U8 | WatchType::U16 | WatchType::U32 => format!("{}", word),
+            WatchType::I8 => format!("{}", word as u8 as i8),
+            WatchType::I16 => format!("{}", word as u16 as i16),
+            WatchType::I32 => format!("{}", word as i32),
+            WatchType::F32 => format!("{}", f32::from_bits(word)),
+        }
+    }
+}
+
+impl FromStr for Watch {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Watch> {
+        let (name, location) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected name=address[:type], got '{}'", s))?;
+        let (address, ty) = location.split_once(':').unwrap_or((location, "i32"));
+        let ty = match ty {
+            "u8" => WatchType::U8,
+            "i8" => WatchType::I8,
+            "u16" => WatchType::U16,
+            "i16" => WatchType::I16,
+            "u32" => WatchType::U32,
+            "i32" => WatchType::I32,
+            "f32" => WatchType::F32,
+            _ => bail!("Unknown watch type '{}'", ty),
+        };
+        Ok(Watch {
+            name: name.to_string(),
+            address: parse_address(address)?,
+            ty,
+            value: String::new(),
+            last_change: 0,
+        })
+    }
+}
+
+fn parse_address(s: &str) -> Result<usize> {
+    let address = if let Some(hex) = s.strip_prefix("0x") {
+        usize::from_str_radix(hex, 16)
+    } else {
+        s.parse()
+    };
+    address.map_err(|_| anyhow!("Invalid address '{}'", s))
+}
+
+/// Parses `address[:length]` into a memory range, the length defaulting to 256 bytes.
+pub fn parse_memory_range(s: &str) -> Result<Range<usize>> {
+    let (start, length) = match s.split_once(':') {
+        Some((start, length)) => (parse_address(start)?, parse_address(length)?),
+        None => (parse_address(s)?, 256),
+    };
+    Ok(start..start + length)
+}
+
+/// A terminal view of the memory of the running cart, organised by the memory map.
+///
+/// The memory is read after every frame, but the view is only redrawn ten times per
+/// second. Bytes that changed since the last redraw are highlighted.
+pub struct MemoryInspector {
+    watches: Vec<Watch>,
+    dump_range: Range<usize>,
+    previous: Vec<u8>,
+    last_draw: Option<Instant>,
+    last_draw_frame: u32,
+}
+
+impl Default for MemoryInspector {
+    fn default() -> MemoryInspector {
+        MemoryInspector::new()
+    }
+}
+
+impl MemoryInspector {
+    pub fn new() -> MemoryInspector {
+        MemoryInspector {
+            watches: vec![],
+            dump_range: 0x14000..0x14100,
+            previous: vec![],
+            last_draw: None,
+            last_draw_frame: 0,
+        }
+    }
+
+    pub fn add_watch(&mut self, watch: Watch) {
+        self.watches.push(watch);
+    }
+
+    /// Sets the range of user memory shown as a hex dump, 0x14000-0x14100 by default.
+    pub fn set_dump_range(&mut self, range: Range<usize>) {
+        self.dump_range = range;
+    }
+
+    pub(crate) fn update(&mut self, memory: &[u8], frame: u32) {
+        for watch in &mut self.watches {
+            let value = match memory.get(watch.address..watch.address + watch.ty.size()) {
+                Some(bytes) => watch.ty.format(bytes),
+                None => "out of range".to_string(),
+            };
+            if value != watch.value {
+                watch.value = value;
+                watch.last_change = frame;
+            }
+        }
+
+        if self
+            .last_draw
+            .map(|time| time.elapsed() < REDRAW_INTERVAL)
+            .unwrap_or(false)
+        {
+            return;
+        }
+        self.last_draw = Some(Instant::now());
+
+        if self.previous.len() != memory.len() {
+            self.previous = memory.to_vec();
+        }
+
+        let view = self.draw(memory, frame);
+        let mut stdout = std::io::stdout().lock();
+        // move the cursor home and clear the screen before each redraw
+        let _ = write!(stdout, "\x1b[H\x1b[2J{}", view);
+        let _ = stdout.flush();
+
+        self.previous.copy_from_slice(memory);
+        self.last_draw_frame = frame;
+    }
+
+    fn draw(&self, memory: &[u8], frame: u32) -> String {
+        let mut out = String::new();
+        let heading = Colour::Cyan.bold();
+        let _ = writeln!(out, "{}", heading.paint(format!("Frame {}", frame)));
+
+        let time = i32::from_le_bytes(memory[0x40..0x44].try_into().unwrap());
+        let _ = writeln!(out, "00040 time         {} ms", time);
+        let _ = write!(out, "00044 gamepads    ");
+        for &buttons in &memory[0x44..0x48] {
+            let _ = write!(out, " {}", format_buttons(buttons));
+        }
+        let _ = write!(out, "\n00048 last frame  ");
+        for &buttons in &memory[0x48..0x4c] {
+            let _ = write!(out, " {}", format_buttons(buttons));
+        }
+        out.push('\n');
+        let _ = writeln!(out, "{}", heading.paint("00050-00070 sound registers"));
+        self.hex_dump(&mut out, memory, 0x50..0x70);
+
+        let framebuffer = &memory[0x78..0x12c78];
+        let mut colors = [false; 256];
+        for &color in framebuffer {
+            colors[color as usize] = true;
+        }
+        let _ = writeln!(
+            out,
+            "00078 framebuffer  {} colors used, {}",
+            colors.iter().filter(|&&used| used).count(),
+            self.changed_bytes(memory, 0x78..0x12c78)
+        );
+        let _ = writeln!(
+            out,
+            "12c78 sndGes base  {:05x}",
+            u32::from_le_bytes(memory[0x12c78..0x12c7c].try_into().unwrap())
+        );
+        let _ = writeln!(
+            out,
+            "13000 palette      {}",
+            self.changed_bytes(memory, 0x13000..0x13400)
+        );
+        let _ = writeln!(
+            out,
+            "13400 font         {}",
+            self.changed_bytes(memory, 0x13400..0x13c00)
+        );
+
+        let _ = writeln!(out, "{}", heading.paint("00000-00040 user memory"));
+        self.hex_dump(&mut out, memory, 0..0x40);
+        let range = self.dump_range.start.min(memory.len())..self.dump_range.end.min(memory.len());
+        let _ = writeln!(
+            out,
+            "{}",
+            heading.paint(format!(
+                "{:05x}-{:05x} user memory, {}",
+                range.start,
+                range.end,
+                self.changed_bytes(memory, 0x14000..memory.len())
+            ))
+        );
+        self.hex_dump(&mut out, memory, range);
+
+        if !self.watches.is_empty() {
+            let _ = writeln!(out, "{}", heading.paint("Watches"));
+            for watch in &self.watches {
+                let value = if watch.last_change > self.last_draw_frame {
+                    Colour::Yellow.paint(&watch.value)
+                } else {
+                    Colour::White.paint(&watch.value)
+                };
+                let _ = writeln!(out, "{:05x} {:16} {}", watch.address, watch.name, value);
+            }
+        }
+
+        out
+    }
+
+    fn changed_bytes(&self, memory: &[u8], range: Range<usize>) -> String {
+        let changed = memory[range.clone()]
+            .iter()
+            .zip(&self.previous[range])
+            .filter(|(a, b)| a != b)
+            .count();
+        format!("{} bytes changed", changed)
+    }
+
+    fn hex_dump(&self, out: &mut String, memory: &[u8], range: Range<usize>) {
+        for line_start in range.clone().step_by(16) {
+            let _ = write!(out, "{:05x} ", line_start);
+            for address in line_start..(line_start + 16).min(range.end) {
+                let byte = format!("{:02x}", memory[address]);
+                if memory[address] != self.previous[address] {
+                    let _ = write!(out, " {}", Colour::Yellow.bold().paint(byte));
+                } else {
+                    let _ = write!(out, " {}", byte);
+                }
+            }
+            out.push('\n');
+        }
+    }
+}
+
+fn format_buttons(buttons: u8) -> String {
+    BUTTON_NAMES
+        .iter()
+        .enumerate()
+        .map(|(bit, &name)| {
+            if buttons & (1 << bit) != 0 {
+                name as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
@@ -1,14 +1,42 @@
+#[cfg(feature = "native")]
+mod audio_output;
+#[cfg(feature = "native")]
+mod capture;
+#[cfg(feature = "native")]
+mod error_report;
 mod filewatcher;
 #[cfg(feature = "native")]
+mod input_log;
+#[cfg(feature = "native")]
+mod inspector;
+#[cfg(feature = "native")]
+mod midi;
+#[cfg(feature = "native")]
+mod profiler;
+#[cfg(feature = "native")]
+mod rewind;
+#[cfg(feature = "native")]
 mod run_native;
 #[cfg(feature = "browser")]
 mod run_web;
+#[cfg(feature = "native")]
+mod save_state;
 
+#[cfg(feature = "native")]
+pub use audio_output::{list_audio_devices, AudioConfig};
+#[cfg(feature = "native")]
+pub use capture::{framebuffer_to_rgb, write_png, write_wav, Y4mWriter};
 pub use filewatcher::FileWatcher;
 #[cfg(feature = "native")]
-pub use run_native::MicroW8;
+pub use inspector::{parse_memory_range, MemoryInspector, Watch};
+#[cfg(feature = "native")]
+pub use midi::{list_midi_ports, MidiIn};
+#[cfg(feature = "native")]
+pub use run_native::{MicroW8, MicroW8Headless, RuntimeConfig};
 #[cfg(feature = "browser")]
 pub use run_web::RunWebServer;
+#[cfg(feature = "native")]
+pub use save_state::SaveState;
 
 use anyhow::Result;
 
@@ -5,12 +5,15 @@ use std::process;
 
 use anyhow::Result;
 use pico_args::Arguments;
-#[cfg(feature = "native")]
-use uw8::MicroW8;
 #[cfg(feature = "browser")]
 use uw8::RunWebServer;
 #[cfg(any(feature = "native", feature = "browser"))]
 use uw8::Runtime;
+#[cfg(feature = "native")]
+use uw8::{
+    parse_memory_range, AudioConfig, MemoryInspector, MicroW8, MicroW8Headless, MidiIn,
+    RuntimeConfig,
+};
 
 fn main() -> Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default()).init();
@@ -27,6 +30,16 @@ fn main() -> Result<()> {
         }
         #[cfg(any(feature = "native", feature = "browser"))]
         Some("run") => run(args),
+        #[cfg(feature = "native")]
+        Some("screenshot") => screenshot(args),
+        #[cfg(feature = "native")]
+        Some("render-audio") => render_audio(args),
+        #[cfg(feature = "native")]
+        Some("record") => record(args),
+        #[cfg(feature = "native")]
+        Some("audio-devices") => audio_devices(),
+        #[cfg(feature = "native")]
+        Some("midi-ports") => midi_ports(),
         Some("pack") => pack(args),
         Some("unpack") => unpack(args),
         Some("compile") => compile(args),
@@ -36,7 +49,17 @@ fn main() -> Result<()> {
             println!();
             println!("Usage:");
             #[cfg(any(feature = "native", feature = "browser"))]
-            println!("  uw8 run [-t/--timeout <frames>] [--b/--browser] [-w/--watch] [-p/--pack] [-u/--uncompressed] [-l/--level] [-o/--output <out-file>] <file>");
+            println!("  uw8 run [-t/--timeout <frames>] [--fuel <units>] [--b/--browser] [-w/--watch] [-p/--pack] [-u/--uncompressed] [-l/--level] [-o/--output <out-file>] <file>");
+            #[cfg(feature = "native")]
+            println!("  uw8 screenshot [-t/--timeout <frames>] [--fuel <units>] [-f/--frame <n>] [-s/--scale <k>] <file> <out-png>");
+            #[cfg(feature = "native")]
+            println!("  uw8 render-audio [-t/--timeout <frames>] [--fuel <units>] [-d/--duration <seconds>] <file> <out-wav>");
+            #[cfg(feature = "native")]
+            println!("  uw8 record [-t/--timeout <frames>] [--fuel <units>] [-f/--frames <n>] [-s/--scale <k>] [-m/--no-audio] -o/--out <dir|file.y4m> <file>");
+            #[cfg(feature = "native")]
+            println!("  uw8 audio-devices");
+            #[cfg(feature = "native")]
+            println!("  uw8 midi-ports");
             println!("  uw8 pack [-u/--uncompressed] [-l/--level] <in-file> <out-file>");
             println!("  uw8 unpack <in-file> <out-file>");
             println!("  uw8 compile [-d/--debug] <in-file> <out-file>");
@@ -55,6 +78,8 @@ fn run(mut args: Arguments) -> Result<()> {
     let watch_mode = args.contains(["-w", "--watch"]);
     #[allow(unused)]
     let timeout: Option<u32> = args.opt_value_from_str(["-t", "--timeout"])?;
+    #[allow(unused)]
+    let fuel: Option<u64> = args.opt_value_from_str("--fuel")?;
 
     let mut config = Config::default();
     if args.contains(["-p", "--pack"]) {
@@ -83,6 +108,51 @@ fn run(mut args: Arguments) -> Result<()> {
 
     #[allow(unused)]
     let disable_audio = args.contains(["-m", "--no-audio"]);
+    #[allow(unused)]
+    let audio_device: Option<String> = args.opt_value_from_str("--audio-device")?;
+    #[allow(unused)]
+    let audio_buffer: Option<u32> = args.opt_value_from_str("--audio-buffer")?;
+    #[allow(unused)]
+    let muted_channels = args.opt_value_from_fn("--mute", parse_channels)?;
+    #[allow(unused)]
+    let solo_channels = args.opt_value_from_fn("--solo", parse_channels)?;
+    #[allow(unused)]
+    let midi_in: Option<String> = args.opt_value_from_str("--midi-in")?;
+    #[allow(unused)]
+    let record_audio: Option<PathBuf> =
+        args.opt_value_from_os_str::<_, _, bool>("--record-audio", |s| Ok(s.into()))?;
+    #[allow(unused)]
+    let fixed_time = args.contains("--fixed-time");
+    #[allow(unused)]
+    let save_states = args.contains("--save-states");
+    #[allow(unused)]
+    let hot_reload = args.contains("--hot-reload");
+    #[allow(unused)]
+    let devkit = args.contains("--devkit");
+    #[allow(unused)]
+    let max_pages: u32 = args
+        .opt_value_from_fn("--max-memory", parse_max_memory)?
+        .unwrap_or(1024 * 16);
+    #[allow(unused)]
+    let sound_sync: Option<String> = args.opt_value_from_str("--sound-sync")?;
+    #[allow(unused)]
+    let start_paused = args.contains("--start-paused");
+    #[allow(unused)]
+    let profile = args.contains("--profile");
+    #[allow(unused)]
+    let inspect = args.contains("--inspect");
+    #[allow(unused)]
+    let inspect_range: Option<String> = args.opt_value_from_str("--inspect-range")?;
+    #[allow(unused)]
+    let watches: Vec<String> = args.values_from_str("--watch-mem")?;
+    #[allow(unused)]
+    let rewind: Option<u32> = args.opt_value_from_str("--rewind")?;
+    #[allow(unused)]
+    let record_input: Option<PathBuf> =
+        args.opt_value_from_os_str::<_, _, bool>("--record-input", |s| Ok(s.into()))?;
+    #[allow(unused)]
+    let replay_input: Option<PathBuf> =
+        args.opt_value_from_os_str::<_, _, bool>("--replay-input", |s| Ok(s.into()))?;
 
     #[cfg(feature = "native")]
     let window_config = {
@@ -104,10 +174,78 @@ fn run(mut args: Arguments) -> Result<()> {
         unimplemented!();
         #[cfg(feature = "native")]
         {
-            let mut microw8 = MicroW8::new(timeout, window_config)?;
+            let config = RuntimeConfig {
+                timeout,
+                fuel,
+                fixed_time,
+            };
+            let mut microw8 = MicroW8::new(config, window_config)?;
+            if save_states {
+                microw8.set_save_state_path(&filename);
+            }
+            if start_paused && (record_input.is_some() || replay_input.is_some()) {
+                eprintln!("--start-paused can't be combined with recording or replaying input");
+                exit(1);
+            }
+            microw8.set_paused(start_paused);
+            if hot_reload {
+                if watch_mode {
+                    microw8.enable_hot_reload();
+                } else {
+                    eprintln!("--hot-reload has no effect without -w/--watch");
+                }
+            }
+            if devkit {
+                microw8.enable_devkit(max_pages);
+            }
+            if let Some(range) = sound_sync {
+                if !devkit {
+                    eprintln!("--sound-sync is only available in devkit mode");
+                    exit(1);
+                }
+                microw8.set_sound_sync_region(parse_memory_range(&range)?);
+            }
+            if profile {
+                microw8.enable_profiler();
+            }
+            if inspect || inspect_range.is_some() || !watches.is_empty() {
+                let mut inspector = MemoryInspector::new();
+                if let Some(range) = inspect_range {
+                    inspector.set_dump_range(parse_memory_range(&range)?);
+                }
+                for watch in watches {
+                    inspector.add_watch(watch.parse()?);
+                }
+                microw8.enable_inspector(inspector);
+            }
+            if let Some(seconds) = rewind {
+                microw8.enable_rewind(seconds);
+            }
             if disable_audio {
                 microw8.disable_audio();
             }
+            microw8.set_audio_config(AudioConfig {
+                device: audio_device,
+                buffer_size: audio_buffer,
+            });
+            if let Some(ref path) = record_audio {
+                microw8.record_audio(path);
+            }
+            if let Some(mask) = muted_channels {
+                microw8.set_muted_channels(mask);
+            }
+            if let Some(mask) = solo_channels {
+                microw8.set_solo_channels(mask);
+            }
+            if let Some(ref port) = midi_in {
+                microw8.set_midi_in(MidiIn::open(port)?);
+            }
+            if let Some(ref path) = record_input {
+                microw8.record_input(path)?;
+            }
+            if let Some(ref path) = replay_input {
+                microw8.replay_input(path)?;
+            }
             Box::new(microw8)
         }
     } else {
@@ -136,6 +274,8 @@ fn run(mut args: Arguments) -> Result<()> {
             first_run = false;
         }
 
+        // in watch mode, the window stays open with an error banner until the cart is
+        // restarted or reloaded
         if let Err(err) = runtime.run_frame() {
             eprintln!("Runtime error: {}", err);
             if !watch_mode {
@@ -147,6 +287,34 @@ fn run(mut args: Arguments) -> Result<()> {
     Ok(())
 }
 
+/// Parses a comma separated list of sound channels (0-3) into a bit mask.
+#[allow(unused)]
+fn parse_channels(s: &str) -> Result<u8> {
+    let mut mask = 0;
+    for channel in s.split(',') {
+        match channel.trim().parse::<u8>() {
+            Ok(channel) if channel < 4 => mask |= 1 << channel,
+            _ => anyhow::bail!("Invalid sound channel '{}', expected 0-3", channel),
+        }
+    }
+    Ok(mask)
+}
+
+/// Parses the devkit memory limit in MB into 64KB pages.
+#[allow(unused)]
+fn parse_max_memory(s: &str) -> Result<u32> {
+    let mb: u32 = s.parse()?;
+    // limited to the 4GB address space of wasm32
+    let pages = mb.saturating_mul(16).min(65536);
+    if pages < 4 {
+        anyhow::bail!(
+            "Invalid memory limit '{}', it has to hold at least the 256KB of a cart",
+            s
+        );
+    }
+    Ok(pages)
+}
+
 #[derive(Default)]
 struct Config {
     pack: Option<uw8_tool::PackConfig>,
@@ -253,6 +421,135 @@ fn start_cart(
     }
 }
 
+#[cfg(feature = "native")]
+fn screenshot(mut args: Arguments) -> Result<()> {
+    let timeout: Option<u32> = args.opt_value_from_str(["-t", "--timeout"])?;
+    let fuel: Option<u64> = args.opt_value_from_str("--fuel")?;
+    let frames: u32 = args.opt_value_from_str(["-f", "--frame"])?.unwrap_or(1);
+    let scale: u32 = args.opt_value_from_str(["-s", "--scale"])?.unwrap_or(1);
+
+    let in_file = args.free_from_os_str::<PathBuf, bool>(|s| Ok(s.into()))?;
+    let out_file = args.free_from_os_str::<PathBuf, bool>(|s| Ok(s.into()))?;
+
+    let cart = load_cart(&in_file, &Config::default()).0?;
+
+    let mut microw8 = MicroW8Headless::new(RuntimeConfig {
+        timeout,
+        fuel,
+        fixed_time: true,
+    })?;
+    microw8.load(&cart)?;
+    for _ in 0..frames {
+        microw8.run_frame()?;
+    }
+
+    if let (Some(framebuffer), Some(palette)) = (microw8.framebuffer(), microw8.palette()) {
+        uw8::write_png(&out_file, framebuffer, palette, scale)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "native")]
+fn render_audio(mut args: Arguments) -> Result<()> {
+    let timeout: Option<u32> = args.opt_value_from_str(["-t", "--timeout"])?;
+    let fuel: Option<u64> = args.opt_value_from_str("--fuel")?;
+    let duration: f32 = args
+        .opt_value_from_str(["-d", "--duration"])?
+        .unwrap_or(10.);
+
+    let in_file = args.free_from_os_str::<PathBuf, bool>(|s| Ok(s.into()))?;
+    let out_file = args.free_from_os_str::<PathBuf, bool>(|s| Ok(s.into()))?;
+
+    let cart = load_cart(&in_file, &Config::default()).0?;
+
+    let mut microw8 = MicroW8Headless::new(RuntimeConfig {
+        timeout,
+        fuel,
+        fixed_time: true,
+    })?;
+    microw8.enable_audio();
+    microw8.load(&cart)?;
+    for _ in 0..(duration * 60.).ceil() as u32 {
+        microw8.run_frame()?;
+    }
+
+    uw8::write_wav(&out_file, &microw8.take_audio())?;
+
+    Ok(())
+}
+
+#[cfg(feature = "native")]
+fn record(mut args: Arguments) -> Result<()> {
+    let timeout: Option<u32> = args.opt_value_from_str(["-t", "--timeout"])?;
+    let fuel: Option<u64> = args.opt_value_from_str("--fuel")?;
+    let frames: u32 = args.opt_value_from_str(["-f", "--frames"])?.unwrap_or(600);
+    let scale: u32 = args.opt_value_from_str(["-s", "--scale"])?.unwrap_or(1);
+    let disable_audio = args.contains(["-m", "--no-audio"]);
+    let out_path = args.value_from_os_str::<_, PathBuf, bool>(["-o", "--out"], |s| Ok(s.into()))?;
+
+    let in_file = args.free_from_os_str::<PathBuf, bool>(|s| Ok(s.into()))?;
+
+    let cart = load_cart(&in_file, &Config::default()).0?;
+
+    let mut microw8 = MicroW8Headless::new(RuntimeConfig {
+        timeout,
+        fuel,
+        fixed_time: true,
+    })?;
+    if !disable_audio {
+        microw8.enable_audio();
+    }
+    microw8.load(&cart)?;
+
+    let (mut video, wav_path) = if out_path.extension().is_some_and(|ext| ext == "y4m") {
+        (
+            Some(uw8::Y4mWriter::create(&out_path, scale)?),
+            out_path.with_extension("wav"),
+        )
+    } else {
+        std::fs::create_dir_all(&out_path)?;
+        (None, out_path.join("audio.wav"))
+    };
+
+    for frame in 0..frames {
+        microw8.run_frame()?;
+        if let (Some(framebuffer), Some(palette)) = (microw8.framebuffer(), microw8.palette()) {
+            if let Some(ref mut video) = video {
+                video.write_frame(framebuffer, palette)?;
+            } else {
+                let path = out_path.join(format!("frame{:05}.png", frame));
+                uw8::write_png(&path, framebuffer, palette, scale)?;
+            }
+        }
+    }
+
+    if let Some(video) = video {
+        video.finish()?;
+    }
+    if !disable_audio {
+        uw8::write_wav(&wav_path, &microw8.take_audio())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "native")]
+fn audio_devices() -> Result<()> {
+    for name in uw8::list_audio_devices()? {
+        println!("{}", name);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "native")]
+fn midi_ports() -> Result<()> {
+    for name in uw8::list_midi_ports()? {
+        println!("{}", name);
+    }
+    Ok(())
+}
+
 fn pack(mut args: Arguments) -> Result<()> {
     let mut pack_config = uw8_tool::PackConfig::default();
 
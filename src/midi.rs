@@ -0,0 +1,96 @@
+use std::sync::mpsc;
+
+use anyhow::{anyhow, Result};
+
+/// Names of all MIDI input ports.
+pub fn list_midi_ports() -> Result<Vec<String>> {
+    let input = midir::MidiInput::new("uw8")?;
+    let mut names = vec![];
+    for port in input.ports() {
+        names.push(input.port_name(&port)?);
+    }
+    Ok(names)
+}
+
+/// Turns note on/off messages on MIDI channels 1-4 into the writes `playNote` makes to
+/// the sound registers of the `sndGes` channels 0-3.
+///
+/// The messages are received from a MIDI port opened with [`MidiIn::open`], or from any
+/// other source sending raw MIDI messages through the channel passed to [`MidiIn::new`].
+pub struct MidiIn {
+    rx: mpsc::Receiver<Vec<u8>>,
+    // keeps the port open as long as messages are read from it
+    _connection: Option<midir::MidiInputConnection<()>>,
+    // the note currently held on each channel, only that one is stopped by a note off
+    notes: [Option<u8>; 4],
+}
+
+impl MidiIn {
+    pub fn new(rx: mpsc::Receiver<Vec<u8>>) -> MidiIn {
+        MidiIn {
+            rx,
+            _connection: None,
+            notes: [None; 4],
+        }
+    }
+
+    /// Opens the first MIDI input port whose name contains `port` (ignoring case).
+    pub fn open(port: &str) -> Result<MidiIn> {
+        let input = midir::MidiInput::new("uw8")?;
+        let pattern = port.to_lowercase();
+        let midi_port = input
+            .ports()
+            .into_iter()
+            .find(|p| {
+                input
+                    .port_name(p)
+                    .map(|name| name.to_lowercase().contains(&pattern))
+                    .unwrap_or(false)
+            })
+            .ok_or_else(|| anyhow!("No MIDI input port matching '{}'", port))?;
+
+        let (tx, rx) = mpsc::channel();
+        let connection = input
+            .connect(
+                &midi_port,
+                "uw8-midi-in",
+                move |_, message, _| {
+                    let _ = tx.send(message.to_vec());
+                },
+                (),
+            )
+            .map_err(|err| anyhow!("Failed to open MIDI port: {}", err))?;
+
+        let mut midi_in = MidiIn::new(rx);
+        midi_in._connection = Some(connection);
+        Ok(midi_in)
+    }
+
+    /// Applies all messages received since the last call to the 32 sound registers.
+    pub(crate) fn apply(&mut self, registers: &mut [u8]) {
+        while let Ok(message) = self.rx.try_recv() {
+            if message.len() < 3 || message[0] & 15 >= 4 {
+                continue;
+            }
+            let channel = (message[0] & 15) as usize;
+            let note = message[1] & 127;
+            let ctrl = channel * 6;
+            match message[0] & 0xf0 {
+                // note on: set the note, set note on and flip the trigger bit
+                0x90 if message[2] > 0 => {
+                    registers[ctrl + 3] = note;
+                    registers[ctrl] = (registers[ctrl] & 0xfe) ^ 3;
+                    self.notes[channel] = Some(note);
+                }
+                // note off or note on with velocity 0: clear note on
+                0x80 | 0x90 => {
+                    if self.notes[channel] == Some(note) {
+                        registers[ctrl] &= 0xfe;
+                        self.notes[channel] = None;
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
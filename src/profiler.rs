@@ -0,0 +1,103 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const FRAME_BUDGET: Duration = Duration::from_micros(16667);
+
+/// Min, average and max of a duration measured once per frame.
+pub(crate) struct FrameStats {
+    min: Duration,
+    max: Duration,
+    total: Duration,
+    count: u32,
+}
+
+impl Default for FrameStats {
+    fn default() -> FrameStats {
+        FrameStats {
+            min: Duration::MAX,
+            max: Duration::ZERO,
+            total: Duration::ZERO,
+            count: 0,
+        }
+    }
+}
+
+impl FrameStats {
+    pub(crate) fn add(&mut self, duration: Duration) {
+        self.min = self.min.min(duration);
+        self.max = self.max.max(duration);
+        self.total += duration;
+        self.count += 1;
+    }
+
+    fn format(&self, name: &str) -> String {
+        if self.count == 0 {
+            return format!("{}: -", name);
+        }
+        let avg = self.total / self.count;
+        format!(
+            "{}: avg {:.2}ms min {:.2}ms max {:.2}ms ({:.1}% of frame)",
+            name,
+            avg.as_secs_f64() * 1000.0,
+            self.min.as_secs_f64() * 1000.0,
+            self.max.as_secs_f64() * 1000.0,
+            avg.as_secs_f64() * 100.0 / FRAME_BUDGET.as_secs_f64()
+        )
+    }
+}
+
+/// Collects the time spent in `upd`, `endFrame` and `snd` and prints the stats once per second.
+///
+/// `snd` is measured on the audio thread, its time is normalized to the duration of one
+/// frame of audio, so that all three numbers can be compared against the same budget.
+pub(crate) struct Profiler {
+    upd: FrameStats,
+    end_frame: FrameStats,
+    snd: Arc<Mutex<FrameStats>>,
+    last_report: Instant,
+}
+
+impl Profiler {
+    pub(crate) fn new() -> Profiler {
+        Profiler {
+            upd: FrameStats::default(),
+            end_frame: FrameStats::default(),
+            snd: Arc::new(Mutex::new(FrameStats::default())),
+            last_report: Instant::now(),
+        }
+    }
+
+    pub(crate) fn snd_stats(&self) -> Arc<Mutex<FrameStats>> {
+        self.snd.clone()
+    }
+
+    pub(crate) fn add_frame(&mut self, timings: FrameTimings) {
+        if let Some(upd) = timings.upd {
+            self.upd.add(upd);
+        }
+        self.end_frame.add(timings.end_frame);
+    }
+
+    pub(crate) fn report(&mut self) {
+        if self.last_report.elapsed() < Duration::from_secs(1) {
+            return;
+        }
+        let snd = std::mem::take(&mut *self.snd.lock().unwrap());
+        println!(
+            "{}\n{}\n{}",
+            self.upd.format("upd     "),
+            self.end_frame.format("endFrame"),
+            snd.format("snd     ")
+        );
+        self.upd = FrameStats::default();
+        self.end_frame = FrameStats::default();
+        self.last_report = Instant::now();
+    }
+}
+
+/// The time the last frame spent in the cart's `upd` and the platform's `endFrame`.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct FrameTimings {
+    pub(crate) upd: Option<Duration>,
+    pub(crate) end_frame: Duration,
+}
@@ -0,0 +1,254 @@
+use std::collections::VecDeque;
+
+use crate::save_state::InstanceState;
+
+/// Ring buffer of the states of the last frames.
+///
+/// Only the newest state is kept in full, each older frame is stored as the
+/// run-length encoded xor delta of its memory to the following frame. As only
+/// small parts of memory usually change between frames, this keeps the buffer
+/// small enough to hold several seconds of frames. When the memory of a devkit cart
+/// grew between two frames, the older frame is stored in full instead.
+pub(crate) struct RewindBuffer {
+    current: Option<(InstanceState, u32)>,
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+struct Entry {
+    frame: u32,
+    memory: EntryMemory,
+    // only the globals, the memory is restored from the delta
+    state: InstanceState,
+}
+
+enum EntryMemory {
+    /// xor delta to the memory of the following frame
+    Delta(Vec<u8>),
+    /// the complete memory, when its size differs from the following frame
+    Full(Vec<u8>),
+}
+
+impl RewindBuffer {
+    pub(crate) fn new(capacity: usize) -> RewindBuffer {
+        RewindBuffer {
+            current: None,
+            entries: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.current = None;
+        self.entries.clear();
+    }
+
+    /// Adds the state after `frame` as the newest entry.
+    pub(crate) fn push(&mut self, state: InstanceState, frame: u32) {
+        if let Some((mut previous, previous_frame)) = self.current.take() {
+            let memory = if previous.memory.len() == state.memory.len() {
+                let delta = encode_delta(&previous.memory, &state.memory);
+                previous.memory = vec![];
+                EntryMemory::Delta(delta)
+            } else {
+                EntryMemory::Full(std::mem::take(&mut previous.memory))
+            };
+            self.entries.push_back(Entry {
+                frame: previous_frame,
+                memory,
+                state: previous,
+            });
+            while self.entries.len() > self.capacity {
+                self.entries.pop_front();
+            }
+        }
+        self.current = Some((state, frame));
+    }
+
+    /// Steps back one frame, returning the state to restore and its frame number.
+    pub(crate) fn pop(&mut self) -> Option<(&InstanceState, u32)> {
+        let entry = self.entries.pop_back()?;
+        let (current, frame) = self.current.as_mut()?;
+        match entry.memory {
+            EntryMemory::Delta(delta) => apply_delta(&mut current.memory, &delta),
+            EntryMemory::Full(memory) => current.memory = memory,
+        }
+        current.globals = entry.state.globals;
+        *frame = entry.frame;
+        Some((current, *frame))
+    }
+}
+
+// The delta is a sequence of runs, each consisting of the number of unchanged bytes
+// followed by the number of changed bytes and the xor of the changed bytes.
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut delta = vec![];
+    let mut index = 0;
+    while index < to.len() {
+        let start = index;
+        while index < to.len() && from[index] == to[index] {
+            index += 1;
+        }
+        let unchanged = index - start;
+        let start = index;
+        // allow short unchanged gaps inside a run to avoid the overhead of a new run
+        loop {
+            let end = (index + 4).min(to.len());
+            if index == end || to[index..end] == from[index..end] {
+                break;
+            }
+            index += 1;
+        }
+        write_varint(&mut delta, unchanged);
+        write_varint(&mut delta, index - start);
+        delta.extend(
+            to[start..index]
+                .iter()
+                .zip(&from[start..index])
+                .map(|(a, b)| a ^ b),
+        );
+    }
+    delta
+}
+
+fn apply_delta(memory: &mut [u8], mut delta: &[u8]) {
+    let mut index = 0;
+    while !delta.is_empty() {
+        index += read_varint(&mut delta);
+        let changed = read_varint(&mut delta);
+        for (dest, xor) in memory[index..index + changed]
+            .iter_mut()
+            .zip(&delta[..changed])
+        {
+            *dest ^= xor;
+        }
+        delta = &delta[changed..];
+        index += changed;
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[0];
+        *data = &data[1..];
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte < 0x80 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(memory: Vec<u8>) -> InstanceState {
+        InstanceState {
+            memory,
+            globals: vec![],
+        }
+    }
+
+    fn frame_memory(frame: u8) -> Vec<u8> {
+        let mut memory = vec![0; 1000];
+        memory[frame as usize * 7] = frame;
+        memory[500..510].fill(frame);
+        memory
+    }
+
+    #[test]
+    fn push_pop_round_trip() {
+        let mut buffer = RewindBuffer::new(10);
+        for frame in 0..5 {
+            buffer.push(state(frame_memory(frame)), frame as u32);
+        }
+        for frame in (0..4).rev() {
+            let (state, popped_frame) = buffer.pop().unwrap();
+            assert_eq!(popped_frame, frame as u32);
+            assert_eq!(state.memory, frame_memory(frame));
+        }
+        assert!(buffer.pop().is_none());
+    }
+
+    #[test]
+    fn drops_oldest_frames_at_capacity() {
+        let mut buffer = RewindBuffer::new(3);
+        for frame in 0..10 {
+            buffer.push(state(frame_memory(frame)), frame as u32);
+        }
+        for frame in [8, 7, 6] {
+            let (state, popped_frame) = buffer.pop().unwrap();
+            assert_eq!(popped_frame, frame as u32);
+            assert_eq!(state.memory, frame_memory(frame));
+        }
+        assert!(buffer.pop().is_none());
+    }
+
+    #[test]
+    fn pop_empty_buffer() {
+        let mut buffer = RewindBuffer::new(10);
+        assert!(buffer.pop().is_none());
+        // the newest state is the current one, there is no older frame to step back to
+        buffer.push(state(frame_memory(0)), 0);
+        assert!(buffer.pop().is_none());
+        buffer.push(state(frame_memory(1)), 1);
+        buffer.clear();
+        assert!(buffer.pop().is_none());
+    }
+
+    #[test]
+    fn memory_growth() {
+        let mut buffer = RewindBuffer::new(10);
+        buffer.push(state(frame_memory(0)), 0);
+        buffer.push(state(vec![1; 2000]), 1);
+        let (state, frame) = buffer.pop().unwrap();
+        assert_eq!(frame, 0);
+        assert_eq!(state.memory, frame_memory(0));
+    }
+
+    #[test]
+    fn runs_longer_than_one_length_byte() {
+        let from = vec![0; 100_000];
+        let mut to = from.clone();
+        // a changed run of 700 bytes with a short unchanged gap inside
+        to[300..1000].fill(1);
+        to[1002] = 5;
+        // after an unchanged run that needs a three byte length
+        to[70_000..70_200].fill(0xff);
+        to[99_999] = 1;
+
+        let delta = encode_delta(&from, &to);
+        assert!(delta.len() < 1000);
+        let mut memory = to.clone();
+        apply_delta(&mut memory, &delta);
+        assert_eq!(memory, from);
+        apply_delta(&mut memory, &delta);
+        assert_eq!(memory, to);
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        let values = [0, 1, 0x7f, 0x80, 0x3fff, 0x4000, 100_000, u32::MAX as usize];
+        let mut data = vec![];
+        for &value in &values {
+            write_varint(&mut data, value);
+        }
+        assert_eq!(data.len(), 1 + 1 + 1 + 2 + 2 + 3 + 3 + 5);
+        let mut data = &data[..];
+        for &value in &values {
+            assert_eq!(read_varint(&mut data), value);
+        }
+        assert!(data.is_empty());
+    }
+}
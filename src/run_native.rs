@@ -1,13 +1,24 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::{mpsc, Arc, Mutex};
 use std::time::Duration;
 use std::{thread, time::Instant};
 
+use crate::audio_output::{open_output, write_output, AudioConfig, AudioRecorder};
+use crate::error_report::{trap_report, BudgetExceeded, ErrorScreen};
+use crate::input_log::{InputFrame, InputPlayback, InputRecorder};
+use crate::inspector::MemoryInspector;
+use crate::midi::MidiIn;
+use crate::profiler::{FrameStats, FrameTimings, Profiler};
+use crate::rewind::RewindBuffer;
+use crate::save_state::{InstanceState, SaveState};
 use anyhow::{anyhow, Result};
 use cpal::traits::*;
 use rubato::Resampler;
 use uw8_window::{Window, WindowConfig};
 use wasmtime::{
-    Engine, GlobalType, Memory, MemoryType, Module, Mutability, Store, TypedFunc, ValType,
+    Engine, ExternType, GlobalType, Instance, Memory, MemoryType, Module, Mutability, Store, Trap,
+    TypedFunc, ValType,
 };
 
 pub struct MicroW8 {
@@ -15,20 +26,59 @@ pub struct MicroW8 {
     stream: Option<cpal::Stream>,
     engine: Engine,
     loader_module: Module,
+    // compiled on the first load, once it is known whether its globals need to be exported
+    platform_module: Option<Module>,
     disable_audio: bool,
+    audio_config: AudioConfig,
+    audio_recorder: Option<AudioRecorder>,
     module_data: Option<Vec<u8>>,
-    timeout: u32,
+    limit: CpuLimit,
+    fixed_time: bool,
+    frame: u32,
+    input_recorder: Option<InputRecorder>,
+    input_playback: Option<InputPlayback>,
+    input_log_notice_shown: bool,
+    save_state_path: Option<PathBuf>,
+    rewind: Option<RewindBuffer>,
+    paused: bool,
+    speed: usize,
+    pending_updates: u32,
+    last_frame: Instant,
+    inspector: Option<MemoryInspector>,
+    profiler: Option<Profiler>,
+    // copy of 0x78-0x13c00 after the last successful frame
+    last_good_screen: Vec<u8>,
+    error_screen: Option<ErrorScreen>,
+    hot_reload: bool,
+    devkit_memory: Option<u32>,
+    sound_sync: Option<Range<usize>>,
+    muted_channels: u8,
+    solo_channels: u8,
+    midi_in: Option<MidiIn>,
     instance: Option<UW8Instance>,
 }
 
+// selectable speeds in quarters of the normal speed
+const SPEEDS: &[u32] = &[1, 2, 4, 8];
+const NORMAL_SPEED: u32 = 4;
+
 struct UW8Instance {
     store: Store<()>,
     memory: Memory,
+    instance: Instance,
+    platform_instance: Instance,
     end_frame: TypedFunc<(), ()>,
     update: Option<TypedFunc<(), ()>>,
-    start_time: Instant,
+    limit: CpuLimit,
+    /// virtual time in ms since the start of the cart, slowed down or stopped
+    /// according to the speed setting
+    time: f64,
     watchdog: Arc<Mutex<UW8WatchDog>>,
-    sound_tx: Option<mpsc::SyncSender<RegisterUpdate>>,
+    timings: FrameTimings,
+    sound_tx: Option<mpsc::SyncSender<SoundMessage>>,
+    sound_sync: Option<Range<usize>>,
+    /// bit mask of the sndGes channels whose volume is passed on to the sound instance
+    audible_channels: u8,
 }
 
 impl Drop for UW8Instance {
@@ -44,17 +94,82 @@ struct UW8WatchDog {
     stop: bool,
 }
 
-impl MicroW8 {
-    pub fn new(timeout: Option<u32>, window_config: WindowConfig) -> Result<MicroW8> {
-        let mut config = wasmtime::Config::new();
-        config.cranelift_opt_level(wasmtime::OptLevel::Speed);
-        if timeout.is_some() {
-            config.epoch_interruption(true);
+/// Frames (1/60s) of wall-clock time `start` and `upd` may run by default before
+/// they are interrupted.
+const DEFAULT_TIMEOUT: u32 = 30;
+
+// number of snd calls per frame of 44100Hz stereo audio
+const SND_CALLS_PER_FRAME: u64 = 44100 * 2 / 60;
+
+/// Settings of the wasm engine that compiles the platform and all carts, which can't be
+/// changed once a runtime is created.
+#[derive(Default, Clone, Copy)]
+pub struct RuntimeConfig {
+    /// Frames (1/60s) of wall-clock time `start` and `upd` may run before they are
+    /// interrupted, 30 if not set.
+    pub timeout: Option<u32>,
+    /// Limit each call of `start`, `upd` and `endFrame` to a fixed amount of fuel instead of
+    /// the wall-clock timeout. `snd` gets the share of one sample of the same budget per call.
+    pub fuel: Option<u64>,
+    /// Advance the time by exactly 1000/60 ms each frame instead of following the wall clock
+    /// and enable NaN canonicalization, so that the same inputs always result in the same memory.
+    /// [`MicroW8Headless`] always runs this way.
+    pub fixed_time: bool,
+}
+
+impl RuntimeConfig {
+    fn limit(&self) -> CpuLimit {
+        match self.fuel {
+            Some(fuel) => CpuLimit::Fuel(fuel),
+            None => CpuLimit::Timeout(self.timeout.unwrap_or(DEFAULT_TIMEOUT)),
         }
-        let engine = wasmtime::Engine::new(&config)?;
+    }
+}
+
+/// How long cart code may run before it is interrupted.
+#[derive(Clone, Copy)]
+enum CpuLimit {
+    /// frames (1/60s) of wall-clock time, counted by a watchdog thread
+    Timeout(u32),
+    /// fuel (roughly one unit per executed wasm instruction) per call of `start`, `upd` and
+    /// the platform's `endFrame`, each `snd` call gets the share of one sample of a frame
+    Fuel(u64),
+}
 
-        let loader_module =
-            wasmtime::Module::new(&engine, include_bytes!("../platform/bin/loader.wasm"))?;
+impl CpuLimit {
+    /// Prepares the store to interrupt the next call once the limit is reached.
+    /// A fuel budget is divided evenly between `calls` calls.
+    fn apply(self, store: &mut Store<()>, calls: u64) -> Result<()> {
+        match self {
+            CpuLimit::Timeout(frames) => store.set_epoch_deadline(frames as u64),
+            CpuLimit::Fuel(fuel) => {
+                let fuel = (fuel / calls).max(1);
+                let remaining = store.consume_fuel(0)?;
+                if remaining < fuel {
+                    store.add_fuel(fuel - remaining)?;
+                } else {
+                    store.consume_fuel(remaining - fuel)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reports running out of fuel as exceeding the budget of the entry point.
+    fn check<T>(self, result: Result<T>, entry_point: &'static str) -> Result<T> {
+        result.map_err(|err| match self {
+            CpuLimit::Fuel(fuel) if err.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) => {
+                err.context(BudgetExceeded { entry_point, fuel })
+            }
+            _ => err,
+        })
+    }
+}
+
+impl MicroW8 {
+    pub fn new(config: RuntimeConfig, window_config: WindowConfig) -> Result<MicroW8> {
+        let limit = config.limit();
+        let (engine, loader_module) = create_engine(limit, config.fixed_time)?;
 
         let window = Window::new(window_config)?;
 
@@ -63,9 +178,33 @@ impl MicroW8 {
             stream: None,
             engine,
             loader_module,
+            platform_module: None,
             disable_audio: false,
+            audio_config: AudioConfig::default(),
+            audio_recorder: None,
             module_data: None,
-            timeout: timeout.unwrap_or(0),
+            limit,
+            fixed_time: config.fixed_time,
+            frame: 0,
+            input_recorder: None,
+            input_playback: None,
+            input_log_notice_shown: false,
+            save_state_path: None,
+            rewind: None,
+            paused: false,
+            speed: SPEEDS.iter().position(|&s| s == NORMAL_SPEED).unwrap(),
+            pending_updates: 0,
+            last_frame: Instant::now(),
+            inspector: None,
+            profiler: None,
+            last_good_screen: vec![],
+            error_screen: None,
+            hot_reload: false,
+            devkit_memory: None,
+            sound_sync: None,
+            muted_channels: 0,
+            solo_channels: 0,
+            midi_in: None,
             instance: None,
         })
     }
@@ -73,75 +212,257 @@ impl MicroW8 {
     pub fn disable_audio(&mut self) {
         self.disable_audio = true;
     }
-}
 
-impl super::Runtime for MicroW8 {
-    fn is_open(&self) -> bool {
-        self.window.is_open()
+    /// Select the audio output device and buffer size.
+    pub fn set_audio_config(&mut self, audio_config: AudioConfig) {
+        self.audio_config = audio_config;
     }
 
-    fn load(&mut self, module_data: &[u8]) -> Result<()> {
-        self.stream = None;
-        self.instance = None;
+    /// Write the sound exactly as it is played to a WAV file, across reloads of the cart.
+    pub fn record_audio(&mut self, path: &Path) {
+        self.audio_recorder = Some(AudioRecorder::new(path));
+    }
 
-        let mut store = wasmtime::Store::new(&self.engine, ());
-        store.set_epoch_deadline(60);
+    /// Log the gamepad state and reset events of every frame to the given file.
+    /// While recording, the pause, speed, rewind and load state hotkeys are disabled.
+    pub fn record_input(&mut self, path: &Path) -> Result<()> {
+        self.input_recorder = Some(InputRecorder::create(path)?);
+        Ok(())
+    }
 
-        let memory = wasmtime::Memory::new(&mut store, MemoryType::new(4, Some(4)))?;
+    /// Replace the live input with the frames logged by `record_input` until the end
+    /// of the log is reached. The same hotkeys as while recording are disabled until then.
+    pub fn replay_input(&mut self, path: &Path) -> Result<()> {
+        self.input_playback = Some(InputPlayback::open(path)?);
+        Ok(())
+    }
 
-        let mut linker = wasmtime::Linker::new(&self.engine);
-        linker.define("env", "memory", memory)?;
+    /// Keep the state of the last `seconds` seconds of frames, so that the cart can be
+    /// played backwards while the rewind key is held.
+    pub fn enable_rewind(&mut self, seconds: u32) {
+        self.rewind = Some(RewindBuffer::new(seconds as usize * 60));
+    }
 
-        let loader_instance = linker.instantiate(&mut store, &self.loader_module)?;
-        let load_uw8 = loader_instance.get_typed_func::<i32, i32>(&mut store, "load_uw8")?;
+    /// Enables the save state hotkeys. Save state slot `n` is stored next to `path` with the
+    /// extension `.state<n>`. Has to be called before loading a cart.
+    pub fn set_save_state_path(&mut self, path: &Path) {
+        self.save_state_path = Some(path.to_path_buf());
+    }
 
-        let platform_data = include_bytes!("../platform/bin/platform.uw8");
-        memory.data_mut(&mut store)[..platform_data.len()].copy_from_slice(platform_data);
-        let platform_length =
-            load_uw8.call(&mut store, platform_data.len() as i32)? as u32 as usize;
-        let platform_module =
-            wasmtime::Module::new(&self.engine, &memory.data(&store)[..platform_length])?;
+    /// Show the memory of the running cart in the terminal, updated after each frame.
+    pub fn enable_inspector(&mut self, inspector: MemoryInspector) {
+        self.inspector = Some(inspector);
+    }
 
-        memory.data_mut(&mut store)[..module_data.len()].copy_from_slice(module_data);
-        let module_length = load_uw8.call(&mut store, module_data.len() as i32)? as u32 as usize;
-        let module = wasmtime::Module::new(&self.engine, &memory.data(&store)[..module_length])?;
+    /// Print min/avg/max of the time spent in `upd`, `endFrame` and `snd` once per second.
+    /// Has to be called before loading a cart to include `snd`.
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
 
-        add_native_functions(&mut linker, &mut store)?;
+    /// When loading a new version of the running cart, keep its user memory (0x0-0x40 and
+    /// 0x14000-0x40000) and the mutable globals that still exist with the same type, and
+    /// skip `start`. The reset hotkey still restarts the cart from scratch.
+    pub fn enable_hot_reload(&mut self) {
+        self.hot_reload = true;
+    }
 
-        let platform_instance = instantiate_platform(&mut linker, &mut store, &platform_module)?;
+    /// Load plain wasm modules (starting with a 0 byte) directly instead of through the
+    /// loader, removing the size restrictions of carts, and allow the memory to grow up to
+    /// `max_pages` pages of 64KB, but at least the 4 pages of a normal cart. The memory starts
+    /// at the size the module imports.
+    pub fn enable_devkit(&mut self, max_pages: u32) {
+        self.devkit_memory = Some(max_pages.max(4));
+    }
 
-        let watchdog = Arc::new(Mutex::new(UW8WatchDog {
-            engine: self.engine.clone(),
-            stop: false,
-        }));
+    /// Copy `range` of the memory to the sound instance after every frame, in addition to the
+    /// sound registers at 0x50-0x70 and timed the same way. Meant for prototyping sound code
+    /// in devkit mode that needs more shared state than the registers.
+    pub fn set_sound_sync_region(&mut self, range: Range<usize>) {
+        self.sound_sync = Some(range);
+    }
 
-        {
-            let watchdog = watchdog.clone();
-            thread::spawn(move || loop {
-                thread::sleep(Duration::from_millis(17));
-                if let Ok(watchdog) = watchdog.lock() {
-                    if watchdog.stop {
-                        break;
-                    }
-                    watchdog.engine.increment_epoch();
-                } else {
-                    break;
-                }
-            });
+    /// Mute the `sndGes` channels set in the bit mask (bit 0 = channel 0) by clearing their
+    /// volume in the sound registers sent to the sound instance. The memory of the cart is
+    /// not changed.
+    pub fn set_muted_channels(&mut self, mask: u8) {
+        self.muted_channels = mask & 15;
+    }
+
+    /// Play only the `sndGes` channels set in the bit mask, unless it is 0.
+    pub fn set_solo_channels(&mut self, mask: u8) {
+        self.solo_channels = mask & 15;
+    }
+
+    /// Play notes received over MIDI on the `sndGes` channels, see [`MidiIn`].
+    /// The notes are written to the sound registers before each call of `upd`.
+    pub fn set_midi_in(&mut self, midi_in: MidiIn) {
+        self.midi_in = Some(midi_in);
+    }
+
+    /// Save states, rewind and hot reload need access to the mutable globals, so only then
+    /// the platform and carts are rewritten to export them. Otherwise the modules run unchanged.
+    fn export_globals(&self) -> bool {
+        self.save_state_path.is_some() || self.rewind.is_some() || self.hot_reload
+    }
+
+    fn platform_module(&mut self) -> Result<Module> {
+        if self.platform_module.is_none() {
+            self.platform_module = Some(load_platform(
+                &self.engine,
+                &self.loader_module,
+                self.limit,
+                self.export_globals(),
+            )?);
         }
+        Ok(self.platform_module.clone().unwrap())
+    }
 
-        let instance = linker.instantiate(&mut store, &module)?;
-        let end_frame = platform_instance.get_typed_func::<(), ()>(&mut store, "endFrame")?;
-        let update = instance.get_typed_func::<(), ()>(&mut store, "upd").ok();
+    fn audible_channels(&self) -> u8 {
+        if self.solo_channels != 0 {
+            self.solo_channels
+        } else {
+            !self.muted_channels & 15
+        }
+    }
+
+    /// While paused, `upd` is only called for frames requested by the step hotkey.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Captures the state of the running cart. The mutable globals are only included if the
+    /// cart was loaded with save state slots, rewind or hot reload enabled.
+    pub fn save_state(&mut self) -> Result<SaveState> {
+        let instance = self
+            .instance
+            .as_mut()
+            .ok_or_else(|| anyhow!("No cart running"))?;
+
+        let sound = if let Some(ref sound_tx) = instance.sound_tx {
+            let (tx, rx) = mpsc::channel();
+            sound_tx
+                .send(SoundMessage::SaveState(tx))
+                .map_err(|_| anyhow!("Sound thread stopped"))?;
+            Some(rx.recv_timeout(Duration::from_secs(1))?)
+        } else {
+            None
+        };
+
+        let main = instance.capture_state();
+        let mut time = [0u8; 4];
+        time.copy_from_slice(&main.memory[64..68]);
+
+        Ok(SaveState {
+            frame: self.frame,
+            time: i32::from_le_bytes(time),
+            main,
+            sound,
+        })
+    }
+
+    pub fn load_state(&mut self, state: &SaveState) -> Result<()> {
+        let instance = self
+            .instance
+            .as_mut()
+            .ok_or_else(|| anyhow!("No cart running"))?;
+
+        instance.restore_state(&state.main)?;
+        if let (Some(sound_tx), Some(sound)) = (&instance.sound_tx, &state.sound) {
+            sound_tx
+                .send(SoundMessage::LoadState(sound.clone()))
+                .map_err(|_| anyhow!("Sound thread stopped"))?;
+        }
+
+        self.frame = state.frame;
+        Ok(())
+    }
+
+    fn state_slot_path(&self, slot: u8) -> Result<PathBuf> {
+        let path = self
+            .save_state_path
+            .as_ref()
+            .ok_or_else(|| anyhow!("Save states are not enabled"))?;
+        Ok(path.with_extension(format!("state{}", slot)))
+    }
 
-        if let Some(start) = instance.get_typed_func::<(), ()>(&mut store, "start").ok() {
-            start.call(&mut store, ())?;
+    fn save_state_slot(&mut self, slot: u8) -> Result<()> {
+        let path = self.state_slot_path(slot)?;
+        self.save_state()?.write(&path)?;
+        println!("Saved state to {}", path.display());
+        Ok(())
+    }
+
+    fn load_state_slot(&mut self, slot: u8) -> Result<()> {
+        let path = self.state_slot_path(slot)?;
+        self.load_state(&SaveState::read(&path)?)?;
+        println!("Loaded state from {}", path.display());
+        Ok(())
+    }
+}
+
+impl super::Runtime for MicroW8 {
+    fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    fn load(&mut self, module_data: &[u8]) -> Result<()> {
+        let previous = if self.hot_reload {
+            self.instance
+                .as_mut()
+                .map(|instance| (instance.capture_state(), instance.time))
+        } else {
+            None
+        };
+
+        // when hot reloading, the running cart is only replaced once the new version is ready,
+        // so that it keeps its state if the new version fails to load
+        if previous.is_none() {
+            self.stream = None;
+            self.instance = None;
         }
 
+        let platform_module = self.platform_module()?;
+        let (mut instance, module) = UW8Instance::new(
+            &self.engine,
+            &self.loader_module,
+            &platform_module,
+            module_data,
+            self.limit,
+            self.devkit_memory,
+            self.export_globals(),
+        )?;
+
+        if let Some((ref state, time)) = previous {
+            state.restore_user_state(
+                &mut instance.store,
+                instance.memory,
+                &[instance.platform_instance, instance.instance],
+            )?;
+            instance.time = time;
+            self.stream = None;
+            self.instance = None;
+        } else if let Err(err) = instance.run_start() {
+            // show the screen as far as start got with the error banner until the cart is restarted
+            let memory = instance.memory.data(&instance.store);
+            self.error_screen = Some(ErrorScreen::new(&memory[0x78..0x13c00], &err.to_string()));
+            self.module_data = Some(module_data.into());
+            return Err(err);
+        }
+        self.error_screen = None;
+        self.last_good_screen.clear();
+
         let (sound_tx, stream) = if self.disable_audio {
             (None, None)
         } else {
-            match init_sound(&self.engine, &platform_module, &module) {
+            let mut options = SoundOptions {
+                limit: self.limit,
+                devkit_memory: self.devkit_memory,
+                audio_config: &self.audio_config,
+                recorder: self.audio_recorder.as_mut(),
+                snd_stats: self.profiler.as_ref().map(|profiler| profiler.snd_stats()),
+            };
+            match init_sound(&self.engine, &platform_module, &module, &mut options) {
                 Ok(sound) => {
                     sound.stream.play()?;
                     (Some(sound.tx), Some(sound.stream))
@@ -153,78 +474,599 @@ impl super::Runtime for MicroW8 {
             }
         };
 
-        self.instance = Some(UW8Instance {
-            store,
-            memory,
-            end_frame,
-            update,
-            start_time: Instant::now(),
-            watchdog,
-            sound_tx,
-        });
+        instance.sound_tx = sound_tx;
+        instance.sound_sync = self.sound_sync.clone();
+        instance.audible_channels = self.audible_channels();
+        self.instance = Some(instance);
+        if previous.is_none() {
+            self.frame = 0;
+        }
+        self.pending_updates = 0;
+        self.last_frame = Instant::now();
+        if let Some(ref mut rewind) = self.rewind {
+            rewind.clear();
+        }
         self.stream = stream;
         self.module_data = Some(module_data.into());
         Ok(())
     }
 
     fn run_frame(&mut self) -> Result<()> {
-        let input = self.window.begin_frame();
+        let mut input = self.window.begin_frame();
+
+        if let Some(ref mut playback) = self.input_playback {
+            if let Some(frame) = playback.next_frame() {
+                input.gamepads = frame.gamepads;
+                input.reset = frame.reset;
+            } else {
+                println!("Input replay finished");
+                self.input_playback = None;
+            }
+        }
+
+        if let Some(ref mut recorder) = self.input_recorder {
+            recorder.record(InputFrame {
+                gamepads: input.gamepads,
+                reset: input.reset,
+            })?;
+        }
+
+        // the input log has one record per frame and none of the state of the cart, so
+        // everything that changes the number of upd calls per frame or restores an older
+        // state would make a replay diverge from the recording
+        if self.input_recorder.is_some() || self.input_playback.is_some() {
+            let disabled = input.pause
+                || input.step
+                || input.slower
+                || input.faster
+                || input.rewind
+                || input.load_state.is_some();
+            if disabled && !self.input_log_notice_shown {
+                println!("Pause, speed, rewind and loading states are disabled while recording or replaying input");
+                self.input_log_notice_shown = true;
+            }
+            input.pause = false;
+            input.step = false;
+            input.slower = false;
+            input.faster = false;
+            input.rewind = false;
+            input.load_state = None;
+        }
 
         if input.reset {
             if let Some(module_data) = self.module_data.take() {
+                // drop the running instance first, so that hot reload doesn't keep its state
+                self.instance = None;
                 self.load(&module_data)?;
             }
         }
 
+        if let Some(slot) = input.save_state {
+            if let Err(err) = self.save_state_slot(slot) {
+                eprintln!("Failed to save state: {}", err);
+            }
+        }
+        if let Some(slot) = input.load_state {
+            if let Err(err) = self.load_state_slot(slot) {
+                eprintln!("Failed to load state: {}", err);
+            }
+        }
+
+        if input.pause {
+            self.paused = !self.paused;
+            println!("{}", if self.paused { "Paused" } else { "Resumed" });
+        }
+        let previous_speed = self.speed;
+        if input.slower {
+            self.speed = self.speed.saturating_sub(1);
+        }
+        if input.faster {
+            self.speed = (self.speed + 1).min(SPEEDS.len() - 1);
+        }
+        if self.speed != previous_speed {
+            let speed = SPEEDS[self.speed] as f32 / NORMAL_SPEED as f32;
+            println!("Speed: {}x", speed);
+        }
+        if let Some(channel) = input.mute {
+            self.muted_channels ^= 1 << channel;
+        }
+        if let Some(channel) = input.solo {
+            self.solo_channels ^= 1 << channel;
+        }
+        if input.mute.is_some() || input.solo.is_some() {
+            let audible = self.audible_channels();
+            let channels: Vec<_> = (0..4)
+                .map(|ch| {
+                    if audible & (1 << ch) != 0 {
+                        ch.to_string()
+                    } else {
+                        "-".to_string()
+                    }
+                })
+                .collect();
+            println!("Channels: {}", channels.join(" "));
+            if let Some(ref mut instance) = self.instance {
+                instance.audible_channels = audible;
+            }
+        }
+
         let now = Instant::now();
+        let elapsed = now - self.last_frame;
+        self.last_frame = now;
         let mut result = Ok(());
         if let Some(mut instance) = self.instance.take() {
-            let time = (now - instance.start_time).as_millis() as i32;
-            let next_frame = {
-                let offset = ((time as u32 as i64 * 6) % 100 - 50) / 6;
+            let next_frame = if self.paused || SPEEDS[self.speed] != NORMAL_SPEED {
+                now + Duration::from_micros(16667)
+            } else {
+                let offset = ((instance.time as i64 as u32 as i64 * 6) % 100 - 50) / 6;
                 let max = now + Duration::from_millis(17);
                 let next_center = now + Duration::from_millis((16 - offset) as u64);
                 next_center.min(max)
             };
 
-            {
-                let mem = instance.memory.data_mut(&mut instance.store);
-                mem[64..68].copy_from_slice(&time.to_le_bytes());
-                mem[68..72].copy_from_slice(&input.gamepads);
-            }
+            match self.rewind {
+                Some(ref mut rewind) if input.rewind => {
+                    if let Some((state, frame)) = rewind.pop() {
+                        result = instance.restore_state(state);
+                        self.frame = frame;
+                    }
+                }
+                _ => {
+                    // at speeds below 1x, upd is only called every few frames, at 2x twice per frame
+                    let (updates, time_step) = if self.paused {
+                        (input.step as u32, 1000.0 / 60.0)
+                    } else {
+                        self.pending_updates += SPEEDS[self.speed];
+                        let updates = self.pending_updates / NORMAL_SPEED;
+                        self.pending_updates %= NORMAL_SPEED;
+                        let time_step = elapsed.as_secs_f64() * 1000.0 * SPEEDS[self.speed] as f64
+                            / NORMAL_SPEED as f64;
+                        (updates, time_step)
+                    };
+                    let start_time = instance.time;
+                    if !self.paused || input.step {
+                        instance.time += time_step;
+                    }
+
+                    for i in 0..updates {
+                        let time = if self.fixed_time {
+                            fixed_frame_time(self.frame)
+                        } else {
+                            (start_time + time_step * (i + 1) as f64 / updates as f64) as i32
+                        };
+                        self.frame = self.frame.wrapping_add(1);
+
+                        if let Some(ref mut midi_in) = self.midi_in {
+                            midi_in
+                                .apply(&mut instance.memory.data_mut(&mut instance.store)[80..112]);
+                        }
+                        result = instance.run_frame(time, input.gamepads);
+                        if result.is_err() {
+                            break;
+                        }
 
-            instance.store.set_epoch_deadline(self.timeout as u64);
-            if let Some(ref update) = instance.update {
-                if let Err(err) = update.call(&mut instance.store, ()) {
-                    result = Err(err);
+                        if let Some(ref mut profiler) = self.profiler {
+                            profiler.add_frame(instance.timings);
+                        }
+
+                        if let Some(ref mut rewind) = self.rewind {
+                            rewind.push(instance.capture_state(), self.frame);
+                        }
+                    }
                 }
             }
-            instance.end_frame.call(&mut instance.store, ())?;
+
+            if let Some(ref mut profiler) = self.profiler {
+                profiler.report();
+            }
+
+            if let Some(ref mut inspector) = self.inspector {
+                inspector.update(instance.memory.data(&instance.store), self.frame);
+            }
 
             let memory = instance.memory.data(&instance.store);
+            match result {
+                Ok(()) => {
+                    self.last_good_screen.clear();
+                    self.last_good_screen
+                        .extend_from_slice(&memory[0x78..0x13c00]);
+                    self.window
+                        .end_frame(instance.framebuffer(), instance.palette(), next_frame);
+                    self.instance = Some(instance);
+                }
+                Err(ref err) => {
+                    // keep the last good frame on screen, unless the cart failed in its first frame
+                    let screen = if self.last_good_screen.is_empty() {
+                        &memory[0x78..0x13c00]
+                    } else {
+                        &self.last_good_screen
+                    };
+                    self.error_screen = Some(ErrorScreen::new(screen, &err.to_string()));
+                    // the sound instance would keep playing the last notes
+                    self.stream = None;
+                }
+            }
+        }
 
-            let mut sound_regs = [0u8; 32];
-            sound_regs.copy_from_slice(&memory[80..112]);
-            if let Some(ref sound_tx) = instance.sound_tx {
-                let _ = sound_tx.send(RegisterUpdate {
-                    time,
-                    data: sound_regs,
-                });
+        if let (None, Some(error_screen)) = (&self.instance, &self.error_screen) {
+            self.window.end_frame(
+                &error_screen.framebuffer,
+                &error_screen.palette,
+                now + Duration::from_micros(16667),
+            );
+        }
+
+        result
+    }
+}
+
+/// A MicroW8 runtime without window or audio output.
+///
+/// Carts are instantiated exactly like in [`MicroW8`], but each call to `run_frame`
+/// advances the time by a fixed 1/60s step, independent of the wall clock, and NaN
+/// canonicalization is enabled so that runs are reproducible. After each frame, the
+/// framebuffer and palette can be read back for further processing. Optionally, the
+/// sound of each frame can be rendered into a buffer as well.
+pub struct MicroW8Headless {
+    engine: Engine,
+    loader_module: Module,
+    platform_module: Module,
+    limit: CpuLimit,
+    instance: Option<UW8Instance>,
+    frame: u32,
+    gamepads: [u8; 4],
+    enable_audio: bool,
+    sound: Option<SoundInstance>,
+    sample_index: i32,
+    audio: Vec<f32>,
+}
+
+impl MicroW8Headless {
+    pub fn new(config: RuntimeConfig) -> Result<MicroW8Headless> {
+        let limit = config.limit();
+        let (engine, loader_module) = create_engine(limit, true)?;
+        let platform_module = load_platform(&engine, &loader_module, limit, false)?;
+
+        Ok(MicroW8Headless {
+            engine,
+            loader_module,
+            platform_module,
+            limit,
+            instance: None,
+            frame: 0,
+            gamepads: [0; 4],
+            enable_audio: false,
+            sound: None,
+            sample_index: 0,
+            audio: vec![],
+        })
+    }
+
+    /// Render the sound output of each frame at 44100Hz stereo, to be retrieved
+    /// with `take_audio`. Has to be called before loading a cart.
+    pub fn enable_audio(&mut self) {
+        self.enable_audio = true;
+    }
+
+    /// Returns the interleaved stereo samples rendered since the last call.
+    pub fn take_audio(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.audio)
+    }
+
+    /// Sets the gamepad state written to 0x44 for all following frames.
+    pub fn set_gamepads(&mut self, gamepads: [u8; 4]) {
+        self.gamepads = gamepads;
+    }
+
+    /// The number of frames run since the cart was loaded.
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// The 320x240 8bpp framebuffer as of the end of the last frame.
+    pub fn framebuffer(&self) -> Option<&[u8]> {
+        self.instance
+            .as_ref()
+            .map(|instance| instance.framebuffer())
+    }
+
+    /// The 256 entry 32bpp palette as of the end of the last frame.
+    pub fn palette(&self) -> Option<&[u8]> {
+        self.instance.as_ref().map(|instance| instance.palette())
+    }
+}
+
+impl super::Runtime for MicroW8Headless {
+    fn is_open(&self) -> bool {
+        true
+    }
+
+    fn load(&mut self, module_data: &[u8]) -> Result<()> {
+        self.instance = None;
+        self.sound = None;
+        self.frame = 0;
+        self.sample_index = 0;
+        self.audio.clear();
+
+        let (mut instance, module) = UW8Instance::new(
+            &self.engine,
+            &self.loader_module,
+            &self.platform_module,
+            module_data,
+            self.limit,
+            None,
+            false,
+        )?;
+        instance.run_start()?;
+        if self.enable_audio {
+            self.sound = Some(SoundInstance::new(
+                &self.engine,
+                &self.platform_module,
+                &module,
+                self.limit,
+                None,
+            )?);
+        }
+        self.instance = Some(instance);
+        Ok(())
+    }
+
+    fn run_frame(&mut self) -> Result<()> {
+        if let Some(mut instance) = self.instance.take() {
+            let time = fixed_frame_time(self.frame);
+            instance.run_frame(time, self.gamepads)?;
+
+            if let Some(ref mut sound) = self.sound {
+                let mut registers = [0u8; 32];
+                registers.copy_from_slice(&instance.memory.data(&instance.store)[80..112]);
+                {
+                    let mem = sound.memory.data_mut(&mut sound.store);
+                    mem[64..68].copy_from_slice(&time.to_le_bytes());
+                    mem[80..112].copy_from_slice(&registers);
+                }
+
+                if let CpuLimit::Timeout(_) = self.limit {
+                    self.limit.apply(&mut sound.store, 1)?;
+                }
+                for _ in 0..SND_CALLS_PER_FRAME {
+                    self.audio.push(sound.call(self.sample_index)?);
+                    self.sample_index = self.sample_index.wrapping_add(1);
+                }
             }
 
-            let framebuffer_mem = &memory[120..(120 + 320 * 240)];
-            let palette_mem = &memory[0x13000..];
-            self.window
-                .end_frame(framebuffer_mem, palette_mem, next_frame);
+            self.frame += 1;
+            self.instance = Some(instance);
+        }
+        Ok(())
+    }
+}
+
+/// Creates the engine and compiles the loader, which is then shared by all carts loaded
+/// with this engine, like the platform compiled by `load_platform`.
+fn create_engine(limit: CpuLimit, deterministic: bool) -> Result<(Engine, Module)> {
+    let mut config = wasmtime::Config::new();
+    config.cranelift_opt_level(wasmtime::OptLevel::Speed);
+    // keep compiled modules on disk, so that unchanged carts and the platform don't have to be
+    // compiled again on the next start
+    if let Err(err) = config.cache_config_load_default() {
+        eprintln!("Failed to enable module cache: {}", err);
+    }
+    match limit {
+        CpuLimit::Timeout(_) => config.epoch_interruption(true),
+        CpuLimit::Fuel(_) => config.consume_fuel(true),
+    };
+    if deterministic {
+        config.cranelift_nan_canonicalization(true);
+    }
+    let engine = wasmtime::Engine::new(&config)?;
+
+    let loader_module =
+        wasmtime::Module::new(&engine, include_bytes!("../platform/bin/loader.wasm"))?;
+
+    Ok((engine, loader_module))
+}
+
+fn load_platform(
+    engine: &Engine,
+    loader_module: &Module,
+    limit: CpuLimit,
+    export_globals: bool,
+) -> Result<Module> {
+    let mut store = new_store(engine, limit)?;
+    let memory = wasmtime::Memory::new(&mut store, MemoryType::new(4, Some(4)))?;
+
+    let mut linker = wasmtime::Linker::new(engine);
+    linker.define("env", "memory", memory)?;
+    let loader_instance = linker.instantiate(&mut store, loader_module)?;
+    let load_uw8 = loader_instance.get_typed_func::<i32, i32>(&mut store, "load_uw8")?;
+
+    let platform_data = include_bytes!("../platform/bin/platform.uw8");
+    memory.data_mut(&mut store)[..platform_data.len()].copy_from_slice(platform_data);
+    let platform_length = load_uw8.call(&mut store, platform_data.len() as i32)? as u32 as usize;
+    compile_module(
+        engine,
+        &memory.data(&store)[..platform_length],
+        export_globals,
+    )
+}
+
+fn fixed_frame_time(frame: u32) -> i32 {
+    (frame as u64 * 1000 / 60) as i32
+}
+
+impl UW8Instance {
+    fn new(
+        engine: &Engine,
+        loader_module: &Module,
+        platform_module: &Module,
+        module_data: &[u8],
+        limit: CpuLimit,
+        devkit_memory: Option<u32>,
+        export_globals: bool,
+    ) -> Result<(UW8Instance, Module)> {
+        let mut store = new_store(engine, limit)?;
+
+        let memory = new_memory(&mut store, devkit_memory)?;
+
+        let mut linker = wasmtime::Linker::new(engine);
+        linker.define("env", "memory", memory)?;
+
+        let module = if devkit_memory.is_some() && module_data.first() == Some(&0) {
+            compile_module(engine, module_data, export_globals)?
+        } else {
+            let loader_instance = linker.instantiate(&mut store, loader_module)?;
+            let load_uw8 = loader_instance.get_typed_func::<i32, i32>(&mut store, "load_uw8")?;
+
+            memory.data_mut(&mut store)[..module_data.len()].copy_from_slice(module_data);
+            let module_length =
+                load_uw8.call(&mut store, module_data.len() as i32)? as u32 as usize;
+            compile_module(
+                engine,
+                &memory.data(&store)[..module_length],
+                export_globals,
+            )?
+        };
+        if devkit_memory.is_some() {
+            grow_memory_for(&mut store, memory, &module)?;
+        }
+
+        add_native_functions(&mut linker, &mut store)?;
+
+        let platform_instance = instantiate_platform(&mut linker, &mut store, platform_module)?;
+
+        let watchdog = Arc::new(Mutex::new(UW8WatchDog {
+            engine: engine.clone(),
+            stop: false,
+        }));
+
+        if let CpuLimit::Timeout(_) = limit {
+            let watchdog = watchdog.clone();
+            thread::spawn(move || loop {
+                thread::sleep(Duration::from_millis(17));
+                if let Ok(watchdog) = watchdog.lock() {
+                    if watchdog.stop {
+                        break;
+                    }
+                    watchdog.engine.increment_epoch();
+                } else {
+                    break;
+                }
+            });
+        }
+
+        let instance = linker.instantiate(&mut store, &module)?;
+        let end_frame = platform_instance.get_typed_func::<(), ()>(&mut store, "endFrame")?;
+        let update = instance.get_typed_func::<(), ()>(&mut store, "upd").ok();
+
+        Ok((
+            UW8Instance {
+                store,
+                memory,
+                instance,
+                platform_instance,
+                end_frame,
+                update,
+                limit,
+                time: 0.0,
+                watchdog,
+                timings: FrameTimings::default(),
+                sound_tx: None,
+                sound_sync: None,
+                audible_channels: 15,
+            },
+            module,
+        ))
+    }
 
-            if result.is_ok() {
-                self.instance = Some(instance);
+    /// Calls the `start` function of the cart, if it has one.
+    fn run_start(&mut self) -> Result<()> {
+        if let Ok(start) = self
+            .instance
+            .get_typed_func::<(), ()>(&mut self.store, "start")
+        {
+            if let CpuLimit::Fuel(_) = self.limit {
+                self.limit.apply(&mut self.store, 1)?;
             }
+            self.limit
+                .check(start.call(&mut self.store, ()), "start")
+                .map_err(|err| trap_report(err, "start"))?;
         }
+        Ok(())
+    }
 
-        result?;
+    fn run_frame(&mut self, time: i32, gamepads: [u8; 4]) -> Result<()> {
+        {
+            let mem = self.memory.data_mut(&mut self.store);
+            mem[64..68].copy_from_slice(&time.to_le_bytes());
+            mem[68..72].copy_from_slice(&gamepads);
+        }
+
+        let mut result = Ok(());
+        self.limit.apply(&mut self.store, 1)?;
+        self.timings.upd = None;
+        if let Some(ref update) = self.update {
+            let start = Instant::now();
+            if let Err(err) = self.limit.check(update.call(&mut self.store, ()), "upd") {
+                result = Err(trap_report(err, "upd"));
+            }
+            self.timings.upd = Some(start.elapsed());
+        }
+        let start = Instant::now();
+        self.limit.apply(&mut self.store, 1)?;
+        let end_frame = self.end_frame.call(&mut self.store, ());
+        self.limit
+            .check(end_frame, "endFrame")
+            .map_err(|err| trap_report(err, "endFrame"))?;
+        self.timings.end_frame = start.elapsed();
+
+        if let Some(ref sound_tx) = self.sound_tx {
+            let mem = self.memory.data(&self.store);
+            let mut sound_regs = [0u8; 32];
+            sound_regs.copy_from_slice(&mem[80..112]);
+            mask_channel_volumes(&mut sound_regs, self.audible_channels);
+            let sync = self.sound_sync.as_ref().and_then(|range| {
+                let data = mem.get(range.clone())?;
+                Some((range.start, data.to_vec()))
+            });
+            let _ = sound_tx.send(SoundMessage::Registers(RegisterUpdate {
+                time,
+                data: sound_regs,
+                sync,
+            }));
+        }
+
+        result
+    }
+
+    fn capture_state(&mut self) -> InstanceState {
+        InstanceState::capture(
+            &mut self.store,
+            self.memory,
+            &[self.platform_instance, self.instance],
+        )
+    }
+
+    /// Restores memory and globals and continues the time from the one stored in memory.
+    fn restore_state(&mut self, state: &InstanceState) -> Result<()> {
+        state.restore(
+            &mut self.store,
+            self.memory,
+            &[self.platform_instance, self.instance],
+        )?;
+
+        let mut time = [0u8; 4];
+        time.copy_from_slice(&state.memory[64..68]);
+        self.time = i32::from_le_bytes(time) as f64;
         Ok(())
     }
+
+    fn framebuffer(&self) -> &[u8] {
+        &self.memory.data(&self.store)[120..(120 + 320 * 240)]
+    }
+
+    fn palette(&self) -> &[u8] {
+        &self.memory.data(&self.store)[0x13000..0x13400]
+    }
 }
 
 fn add_native_functions(
@@ -277,91 +1119,200 @@ fn instantiate_platform(
     let platform_instance = linker.instantiate(&mut *store, &platform_module)?;
 
     for export in platform_instance.exports(&mut *store) {
-        linker.define(
-            "env",
-            export.name(),
-            export
-                .into_func()
-                .expect("platform surely only exports functions"),
-        )?;
+        let name = export.name();
+        // the only other exports are the globals made visible for save states
+        if let Some(func) = export.into_func() {
+            linker.define("env", name, func)?;
+        }
     }
 
     Ok(platform_instance)
 }
 
+/// Compiles the platform or a cart, optionally making its mutable globals accessible for
+/// save states. Modules walrus fails to parse are used unchanged, their globals are just
+/// not part of save states.
+fn compile_module(engine: &Engine, module: &[u8], export_globals: bool) -> Result<Module> {
+    if !export_globals {
+        return Module::new(engine, module);
+    }
+    match uw8_tool::export_mutable_globals(module) {
+        Ok(module) => Module::new(engine, &module),
+        Err(err) => {
+            eprintln!("Failed to export globals, they won't be saved: {}", err);
+            Module::new(engine, module)
+        }
+    }
+}
+
+/// Clears the volume nibbles at 0x18/0x19 of the sndGes registers for all channels not
+/// set in `audible`.
+fn mask_channel_volumes(registers: &mut [u8; 32], audible: u8) {
+    for channel in 0..4 {
+        if audible & (1 << channel) == 0 {
+            registers[0x18 + channel / 2] &= if channel & 1 == 0 { 0xf0 } else { 0x0f };
+        }
+    }
+}
+
 struct RegisterUpdate {
     time: i32,
     data: [u8; 32],
+    /// address and content of the sync region set with `MicroW8::set_sound_sync_region`
+    sync: Option<(usize, Vec<u8>)>,
+}
+
+enum SoundMessage {
+    Registers(RegisterUpdate),
+    SaveState(mpsc::Sender<InstanceState>),
+    LoadState(InstanceState),
 }
 
 struct Uw8Sound {
     stream: cpal::Stream,
-    tx: mpsc::SyncSender<RegisterUpdate>,
+    tx: mpsc::SyncSender<SoundMessage>,
+}
+
+/// The second instance of a cart, used to generate the sound by calling `snd`, or
+/// `sndGes` if the cart doesn't export its own `snd` function.
+struct SoundInstance {
+    store: Store<()>,
+    memory: Memory,
+    platform_instance: Instance,
+    instance: Instance,
+    snd: TypedFunc<(i32,), f32>,
+    limit: CpuLimit,
+}
+
+impl SoundInstance {
+    fn new(
+        engine: &wasmtime::Engine,
+        platform_module: &wasmtime::Module,
+        module: &wasmtime::Module,
+        limit: CpuLimit,
+        devkit_memory: Option<u32>,
+    ) -> Result<SoundInstance> {
+        let mut store = new_store(engine, limit)?;
+
+        let memory = new_memory(&mut store, devkit_memory)?;
+        if devkit_memory.is_some() {
+            grow_memory_for(&mut store, memory, module)?;
+        }
+
+        let mut linker = wasmtime::Linker::new(engine);
+        linker.define("env", "memory", memory)?;
+        add_native_functions(&mut linker, &mut store)?;
+
+        let platform_instance = instantiate_platform(&mut linker, &mut store, platform_module)?;
+        let instance = linker.instantiate(&mut store, module)?;
+
+        let snd = instance
+            .get_typed_func::<(i32,), f32>(&mut store, "snd")
+            .or_else(|_| platform_instance.get_typed_func::<(i32,), f32>(&mut store, "sndGes"))?;
+
+        Ok(SoundInstance {
+            store,
+            memory,
+            platform_instance,
+            instance,
+            snd,
+            limit,
+        })
+    }
+
+    fn call(&mut self, sample_index: i32) -> Result<f32> {
+        call_snd(&self.snd, &mut self.store, self.limit, sample_index)
+    }
+}
+
+/// Calls `snd`, with the fuel share of a single sample if a fuel budget is set.
+fn call_snd(
+    snd: &TypedFunc<(i32,), f32>,
+    store: &mut Store<()>,
+    limit: CpuLimit,
+    sample_index: i32,
+) -> Result<f32> {
+    if let CpuLimit::Fuel(_) = limit {
+        limit.apply(store, SND_CALLS_PER_FRAME)?;
+    }
+    limit
+        .check(snd.call(store, (sample_index,)), "snd")
+        .map_err(|err| trap_report(err, "snd"))
+}
+
+/// The memory shared by platform and cart, 256KB unless the devkit allows it to grow.
+fn new_memory(store: &mut Store<()>, devkit_memory: Option<u32>) -> Result<Memory> {
+    let max_pages = devkit_memory.unwrap_or(4);
+    Memory::new(store, MemoryType::new(4, Some(max_pages)))
+}
+
+/// Grows the memory to the minimum size imported by a devkit module, eg. for the data
+/// and stack of modules compiled from Rust or C.
+fn grow_memory_for(store: &mut Store<()>, memory: Memory, module: &Module) -> Result<()> {
+    for import in module.imports() {
+        if let ExternType::Memory(ty) = import.ty() {
+            let size = memory.size(&*store);
+            if ty.minimum() > size {
+                memory.grow(&mut *store, ty.minimum() - size)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn new_store(engine: &Engine, limit: CpuLimit) -> Result<Store<()>> {
+    let mut store = Store::new(engine, ());
+    match limit {
+        CpuLimit::Timeout(_) => store.set_epoch_deadline(60),
+        // loading and instantiating the cart is not part of the budget. wasmtime caps the total
+        // fuel ever added to a store at i64::MAX, so leave room for refilling the budget later.
+        CpuLimit::Fuel(_) => store.add_fuel(u64::MAX / 4)?,
+    }
+    Ok(store)
+}
+
+/// The settings of the runtime used by the sound instance and its output stream.
+struct SoundOptions<'a> {
+    limit: CpuLimit,
+    devkit_memory: Option<u32>,
+    audio_config: &'a AudioConfig,
+    recorder: Option<&'a mut AudioRecorder>,
+    /// collects the time spent in `snd` for the profiler
+    snd_stats: Option<Arc<Mutex<FrameStats>>>,
 }
 
 fn init_sound(
     engine: &wasmtime::Engine,
     platform_module: &wasmtime::Module,
     module: &wasmtime::Module,
+    options: &mut SoundOptions,
 ) -> Result<Uw8Sound> {
-    let mut store = wasmtime::Store::new(engine, ());
-    store.set_epoch_deadline(60);
-
-    let memory = wasmtime::Memory::new(&mut store, MemoryType::new(4, Some(4)))?;
+    let SoundInstance {
+        mut store,
+        memory,
+        platform_instance,
+        instance,
+        snd,
+        limit,
+    } = SoundInstance::new(
+        engine,
+        platform_module,
+        module,
+        options.limit,
+        options.devkit_memory,
+    )?;
 
-    let mut linker = wasmtime::Linker::new(engine);
-    linker.define("env", "memory", memory)?;
-    add_native_functions(&mut linker, &mut store)?;
-
-    let platform_instance = instantiate_platform(&mut linker, &mut store, platform_module)?;
-    let instance = linker.instantiate(&mut store, module)?;
-
-    let snd = instance
-        .get_typed_func::<(i32,), f32>(&mut store, "snd")
-        .or_else(|_| platform_instance.get_typed_func::<(i32,), f32>(&mut store, "sndGes"))?;
-
-    let host = cpal::default_host();
-    let device = host
-        .default_output_device()
-        .ok_or_else(|| anyhow!("No audio output device available"))?;
-    let mut configs: Vec<_> = device
-        .supported_output_configs()?
-        .filter(|config| {
-            config.channels() == 2 && config.sample_format() == cpal::SampleFormat::F32
-        })
-        .collect();
-    configs.sort_by_key(|config| {
-        let rate = 44100
-            .max(config.min_sample_rate().0)
-            .min(config.max_sample_rate().0);
-        if rate >= 44100 {
-            rate - 44100
-        } else {
-            (44100 - rate) * 1000
-        }
-    });
-    let config = configs
-        .into_iter()
-        .next()
-        .ok_or_else(|| anyhow!("Could not find float output config"))?;
-    let sample_rate = cpal::SampleRate(44100)
-        .max(config.min_sample_rate())
-        .min(config.max_sample_rate());
-    let config = config.with_sample_rate(sample_rate);
-    let buffer_size = match *config.buffer_size() {
-        cpal::SupportedBufferSize::Unknown => cpal::BufferSize::Default,
-        cpal::SupportedBufferSize::Range { min, max } => {
-            cpal::BufferSize::Fixed(256.max(min).min(max))
-        }
-    };
-    let config = cpal::StreamConfig {
-        buffer_size,
-        ..config.config()
-    };
+    let (device, config, sample_format) = open_output(options.audio_config)?;
+    let channels = config.channels as usize;
 
     let sample_rate = config.sample_rate.0 as usize;
+    let record_tx = match options.recorder {
+        Some(ref mut recorder) => Some(recorder.sender(config.sample_rate.0)?),
+        None => None,
+    };
+    let snd_stats = options.snd_stats.clone();
 
-    let (tx, rx) = mpsc::sync_channel::<RegisterUpdate>(30);
+    let (tx, rx) = mpsc::sync_channel::<SoundMessage>(30);
 
     struct Resampler {
         resampler: rubato::FftFixedIn<f32>,
@@ -384,18 +1335,54 @@ fn init_sound(
     };
 
     let mut sample_index = 0;
+    // snd is called for every sample, so it is not called again after the first error
+    let mut snd_failed = false;
     let mut pending_updates: Vec<RegisterUpdate> = Vec::with_capacity(30);
     let mut current_time = 0;
-    let stream = device.build_output_stream(
+    let mut stereo_buffer: Vec<f32> = vec![];
+    let stream = device.build_output_stream_raw(
         &config,
-        move |mut outer_buffer: &mut [f32], _| {
+        sample_format,
+        move |output: &mut cpal::Data, _| {
+            stereo_buffer.resize(output.len() / channels * 2, 0.0);
+            let mut outer_buffer = &mut stereo_buffer[..];
+            let mut next_sample = |store: &mut Store<()>, sample_index: i32| {
+                if snd_failed {
+                    return 0.0;
+                }
+                call_snd(&snd, store, limit, sample_index).unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    snd_failed = true;
+                    0.0
+                })
+            };
+            let mut snd_time = Duration::ZERO;
+            let mut snd_calls = 0;
             let mut first_update = true;
-            while let Ok(update) = rx.try_recv() {
-                if first_update {
-                    current_time += update.time.wrapping_sub(current_time) / 8;
-                    first_update = false;
+            while let Ok(message) = rx.try_recv() {
+                match message {
+                    SoundMessage::Registers(update) => {
+                        if first_update {
+                            current_time += update.time.wrapping_sub(current_time) / 8;
+                            first_update = false;
+                        }
+                        pending_updates.push(update);
+                    }
+                    SoundMessage::SaveState(reply) => {
+                        let _ = reply.send(InstanceState::capture(
+                            &mut store,
+                            memory,
+                            &[platform_instance, instance],
+                        ));
+                    }
+                    SoundMessage::LoadState(state) => {
+                        if let Err(err) =
+                            state.restore(&mut store, memory, &[platform_instance, instance])
+                        {
+                            eprintln!("Failed to restore sound state: {}", err);
+                        }
+                    }
                 }
-                pending_updates.push(update);
             }
 
             while !outer_buffer.is_empty() {
@@ -406,6 +1393,10 @@ fn init_sound(
                     .any(|u| u.time.wrapping_sub(current_time) <= 0)
                 {
                     let update = pending_updates.remove(0);
+                    if let Some((address, ref data)) = update.sync {
+                        // the sound instance may not have grown its memory as far as the main one
+                        let _ = memory.write(&mut store, address, data);
+                    }
                     memory.write(&mut store, 80, &update.data).unwrap();
                 }
 
@@ -432,13 +1423,16 @@ fn init_sound(
                         if copy_size == 0 {
                             resampler.input_buffers[0].clear();
                             resampler.input_buffers[1].clear();
+                            let start = Instant::now();
                             for _ in 0..resampler.resampler.input_frames_next() {
                                 resampler.input_buffers[0]
-                                    .push(snd.call(&mut store, (sample_index,)).unwrap_or(0.0));
+                                    .push(next_sample(&mut store, sample_index));
                                 resampler.input_buffers[1]
-                                    .push(snd.call(&mut store, (sample_index + 1,)).unwrap_or(0.0));
+                                    .push(next_sample(&mut store, sample_index + 1));
                                 sample_index = sample_index.wrapping_add(2);
+                                snd_calls += 2;
                             }
+                            snd_time += start.elapsed();
 
                             resampler
                                 .resampler
@@ -461,16 +1455,32 @@ fn init_sound(
                         }
                     }
                 } else {
+                    let start = Instant::now();
                     for v in buffer {
-                        *v = snd.call(&mut store, (sample_index,)).unwrap_or(0.0);
+                        *v = next_sample(&mut store, sample_index);
                         sample_index = sample_index.wrapping_add(1);
                     }
+                    snd_time += start.elapsed();
+                    snd_calls += step_size as u32;
                 }
 
                 outer_buffer = &mut outer_buffer[step_size..];
                 current_time =
                     current_time.wrapping_add((step_size * 500 / sample_rate).max(1) as i32);
             }
+
+            if let Some(ref stats) = snd_stats {
+                if snd_calls > 0 {
+                    // normalize to the 44100 * 2 / 60 snd calls of one frame
+                    stats.lock().unwrap().add(snd_time * 1470 / snd_calls);
+                }
+            }
+
+            write_output(output, &stereo_buffer, channels);
+            if let Some(ref record_tx) = record_tx {
+                // rather drop a buffer from the recording than block the audio thread
+                let _ = record_tx.try_send(stereo_buffer.clone());
+            }
         },
         move |err| {
             dbg!(err);
@@ -0,0 +1,317 @@
+use std::fs::File;
+use std::io::{prelude::*, BufWriter};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use wasmtime::{Instance, Memory, Mutability, Store, Val, ValType};
+
+const MAGIC: &[u8; 4] = b"UW8S";
+const VERSION: u8 = 1;
+
+/// A snapshot of a running cart: the memory and mutable globals of the main instance,
+/// the same for the sound instance if audio is enabled, and the current time.
+pub struct SaveState {
+    pub(crate) frame: u32,
+    pub(crate) time: i32,
+    pub(crate) main: InstanceState,
+    pub(crate) sound: Option<InstanceState>,
+}
+
+impl SaveState {
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        out.write_all(&self.frame.to_le_bytes())?;
+        out.write_all(&self.time.to_le_bytes())?;
+        self.main.write(&mut out)?;
+        if let Some(ref sound) = self.sound {
+            out.write_all(&[1])?;
+            sound.write(&mut out)?;
+        } else {
+            out.write_all(&[0])?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<SaveState> {
+        let mut data = vec![];
+        File::open(path)?.read_to_end(&mut data)?;
+        let mut reader = Reader(&data);
+        if reader.bytes(4)? != MAGIC {
+            bail!("Not a MicroW8 save state");
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            bail!("Unsupported save state version {}", version);
+        }
+        let frame = reader.u32()?;
+        let time = reader.u32()? as i32;
+        let main = InstanceState::read(&mut reader)?;
+        let sound = if reader.u8()? != 0 {
+            Some(InstanceState::read(&mut reader)?)
+        } else {
+            None
+        };
+        Ok(SaveState {
+            frame,
+            time,
+            main,
+            sound,
+        })
+    }
+}
+
+/// Memory and mutable globals of one store. Globals are kept in one list per wasm
+/// instance (eg. platform and cart) and are only visible if they were exported by
+/// `uw8_tool::export_mutable_globals`.
+#[derive(Clone)]
+pub(crate) struct InstanceState {
+    pub(crate) memory: Vec<u8>,
+    pub(crate) globals: Vec<Vec<(String, GlobalValue)>>,
+}
+
+impl InstanceState {
+    pub(crate) fn capture(
+        store: &mut Store<()>,
+        memory: Memory,
+        instances: &[Instance],
+    ) -> InstanceState {
+        let globals = instances
+            .iter()
+            .map(|instance| {
+                let globals: Vec<_> = instance
+                    .exports(&mut *store)
+                    .filter(|export| export.name().starts_with(uw8_tool::GLOBAL_EXPORT_PREFIX))
+                    .filter_map(|export| {
+                        let name = export.name().to_string();
+                        export.into_global().map(|global| (name, global))
+                    })
+                    .collect();
+                globals
+                    .into_iter()
+                    .filter_map(|(name, global)| {
+                        GlobalValue::from_val(global.get(&mut *store)).map(|value| (name, value))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        InstanceState {
+            memory: memory.data(&*store).to_vec(),
+            globals,
+        }
+    }
+
+    /// Writes the memory and all globals that still exist with the same type back into the store.
+    ///
+    /// In devkit mode the memory can have grown since the state was captured, any memory
+    /// beyond the saved size is cleared. A state with more memory grows the memory to fit.
+    pub(crate) fn restore(
+        &self,
+        store: &mut Store<()>,
+        memory: Memory,
+        instances: &[Instance],
+    ) -> Result<()> {
+        let size = memory.data_size(&*store);
+        if size < self.memory.len() {
+            let pages = (self.memory.len() - size + 0xffff) / 0x10000;
+            memory.grow(&mut *store, pages as u64).map_err(|_| {
+                anyhow!(
+                    "Memory size mismatch: state needs {} bytes, memory can't grow beyond {}",
+                    self.memory.len(),
+                    size
+                )
+            })?;
+        }
+        let data = memory.data_mut(&mut *store);
+        data[..self.memory.len()].copy_from_slice(&self.memory);
+        data[self.memory.len()..].fill(0);
+
+        self.restore_globals(store, instances)
+    }
+
+    /// Copies only the user memory and the globals into the store, leaving the framebuffer,
+    /// palette and font of the new instance as they are. Used to carry the state of a cart
+    /// over into a newly compiled version of it.
+    pub(crate) fn restore_user_state(
+        &self,
+        store: &mut Store<()>,
+        memory: Memory,
+        instances: &[Instance],
+    ) -> Result<()> {
+        let data = memory.data_mut(&mut *store);
+        for range in [0..0x40, 0x14000..0x40000] {
+            let range = range.start..range.end.min(data.len()).min(self.memory.len());
+            data[range.clone()].copy_from_slice(&self.memory[range]);
+        }
+
+        self.restore_globals(store, instances)
+    }
+
+    /// Restores the globals exported by name that still exist with the same type. Globals
+    /// exported by their position (in carts without a name section) are only restored if
+    /// all of them are unchanged, as adding or removing a global would otherwise shift the
+    /// old values into the wrong globals.
+    fn restore_globals(&self, store: &mut Store<()>, instances: &[Instance]) -> Result<()> {
+        let named_prefix = format!("{}_", uw8_tool::GLOBAL_EXPORT_PREFIX);
+        let by_position = |name: &str| !name.starts_with(&named_prefix);
+        for (instance, globals) in instances.iter().zip(&self.globals) {
+            let saved: Vec<(&str, ValType)> = globals
+                .iter()
+                .filter(|(name, _)| by_position(name))
+                .map(|(name, value)| (name.as_str(), value.ty()))
+                .collect();
+            let current: Vec<_> = instance
+                .exports(&mut *store)
+                .filter(|export| {
+                    export.name().starts_with(uw8_tool::GLOBAL_EXPORT_PREFIX)
+                        && by_position(export.name())
+                })
+                .filter_map(|export| {
+                    let name = export.name().to_string();
+                    export.into_global().map(|global| (name, global))
+                })
+                .collect();
+            let positions_unchanged = saved.len() == current.len()
+                && saved.iter().zip(&current).all(
+                    |(&(saved_name, ref saved_ty), (name, global))| {
+                        let ty = global.ty(&*store);
+                        saved_name == name.as_str()
+                            && ty.mutability() == Mutability::Var
+                            && ty.content() == saved_ty
+                    },
+                );
+            if !positions_unchanged && !saved.is_empty() {
+                eprintln!(
+                    "The globals of the cart changed, only restoring globals with names (eg. compiled with `uw8 compile --debug`)"
+                );
+            }
+
+            for (name, value) in globals {
+                if by_position(name) && !positions_unchanged {
+                    continue;
+                }
+                if let Some(global) = instance.get_global(&mut *store, name) {
+                    let ty = global.ty(&*store);
+                    if ty.mutability() == Mutability::Var && *ty.content() == value.ty() {
+                        global.set(&mut *store, value.to_val())?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, out: &mut impl Write) -> Result<()> {
+        out.write_all(&(self.memory.len() as u32).to_le_bytes())?;
+        out.write_all(&self.memory)?;
+        out.write_all(&(self.globals.len() as u32).to_le_bytes())?;
+        for globals in &self.globals {
+            out.write_all(&(globals.len() as u32).to_le_bytes())?;
+            for (name, value) in globals {
+                out.write_all(&[name.len() as u8])?;
+                out.write_all(name.as_bytes())?;
+                let (tag, bits) = match *value {
+                    GlobalValue::I32(v) => (0, v as u32 as u64),
+                    GlobalValue::I64(v) => (1, v as u64),
+                    GlobalValue::F32(v) => (2, v as u64),
+                    GlobalValue::F64(v) => (3, v),
+                };
+                out.write_all(&[tag])?;
+                out.write_all(&bits.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read(reader: &mut Reader) -> Result<InstanceState> {
+        let memory_size = reader.u32()? as usize;
+        let memory = reader.bytes(memory_size)?.to_vec();
+        let mut globals = vec![];
+        for _ in 0..reader.u32()? {
+            let mut instance_globals = vec![];
+            for _ in 0..reader.u32()? {
+                let name_length = reader.u8()? as usize;
+                let name = String::from_utf8(reader.bytes(name_length)?.to_vec())?;
+                let tag = reader.u8()?;
+                let bits = reader.u64()?;
+                let value = match tag {
+                    0 => GlobalValue::I32(bits as u32 as i32),
+                    1 => GlobalValue::I64(bits as i64),
+                    2 => GlobalValue::F32(bits as u32),
+                    3 => GlobalValue::F64(bits),
+                    _ => bail!("Unknown global type {}", tag),
+                };
+                instance_globals.push((name, value));
+            }
+            globals.push(instance_globals);
+        }
+        Ok(InstanceState { memory, globals })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum GlobalValue {
+    I32(i32),
+    I64(i64),
+    F32(u32),
+    F64(u64),
+}
+
+impl GlobalValue {
+    fn from_val(val: Val) -> Option<GlobalValue> {
+        match val {
+            Val::I32(v) => Some(GlobalValue::I32(v)),
+            Val::I64(v) => Some(GlobalValue::I64(v)),
+            Val::F32(v) => Some(GlobalValue::F32(v)),
+            Val::F64(v) => Some(GlobalValue::F64(v)),
+            _ => None,
+        }
+    }
+
+    fn to_val(self) -> Val {
+        match self {
+            GlobalValue::I32(v) => Val::I32(v),
+            GlobalValue::I64(v) => Val::I64(v),
+            GlobalValue::F32(v) => Val::F32(v),
+            GlobalValue::F64(v) => Val::F64(v),
+        }
+    }
+
+    fn ty(self) -> ValType {
+        match self {
+            GlobalValue::I32(_) => ValType::I32,
+            GlobalValue::I64(_) => ValType::I64,
+            GlobalValue::F32(_) => ValType::F32,
+            GlobalValue::F64(_) => ValType::F64,
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8]> {
+        if self.0.len() < count {
+            return Err(anyhow!("Unexpected end of save state"));
+        }
+        let (bytes, rest) = self.0.split_at(count);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
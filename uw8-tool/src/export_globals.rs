@@ -0,0 +1,44 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use walrus::{ExportItem, GlobalKind};
+
+pub const GLOBAL_EXPORT_PREFIX: &str = "__uw8_global";
+
+/// Exports all mutable globals defined in the module, so that a runtime can read and
+/// restore their values, eg. for save states or when hot reloading a cart.
+///
+/// Globals with a unique name in the name section are exported as `__uw8_global_<name>`,
+/// so that they keep their export when other globals are added or removed. All others
+/// are exported as `__uw8_global<n>`.
+pub fn export_mutable_globals(data: &[u8]) -> Result<Vec<u8>> {
+    let mut module = walrus::Module::from_buffer(data)?;
+
+    let globals: Vec<_> = module
+        .globals
+        .iter()
+        .filter(|global| global.mutable && matches!(global.kind, GlobalKind::Local(_)))
+        .map(|global| (global.id(), global.name.clone()))
+        .collect();
+
+    let mut names = HashSet::new();
+    let duplicates: HashSet<_> = globals
+        .iter()
+        .filter_map(|(_, name)| name.as_ref())
+        .filter(|&name| !names.insert(name))
+        .cloned()
+        .collect();
+
+    for (index, (id, name)) in globals.into_iter().enumerate() {
+        let export_name = match name {
+            // save states store the name length in a single byte
+            Some(name) if !duplicates.contains(&name) && name.len() < 200 => {
+                format!("{}_{}", GLOBAL_EXPORT_PREFIX, name)
+            }
+            _ => format!("{}{}", GLOBAL_EXPORT_PREFIX, index),
+        };
+        module.exports.add(&export_name, ExportItem::Global(id));
+    }
+
+    Ok(module.emit_wasm())
+}
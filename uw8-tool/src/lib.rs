@@ -1,9 +1,13 @@
 mod base_module;
+mod export_globals;
 mod filter_exports;
+mod midi;
 mod pack;
 
 pub use base_module::BaseModule;
+pub use export_globals::{export_mutable_globals, GLOBAL_EXPORT_PREFIX};
 pub use filter_exports::filter_exports;
+pub use midi::{convert_midi, convert_midi_file, MidiConfig, MidiOutputFormat, MidiStats};
 pub use pack::{pack, pack_file, unpack, unpack_file, PackConfig};
 
 pub fn compressed_size(cart: &[u8]) -> f32 {
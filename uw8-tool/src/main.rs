@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use pico_args::Arguments;
 use uw8_tool::BaseModule;
 
@@ -40,6 +40,39 @@ fn main() -> Result<()> {
                 let path: PathBuf = args.free_from_str()?;
                 BaseModule::for_format_version(1)?.write_as_wat(path)?;
             }
+            "midi" => {
+                let mut config = uw8_tool::MidiConfig::default();
+                if let Some(channels) = args.opt_value_from_str::<_, String>("--channels")? {
+                    config = config.with_channels(parse_midi_channels(&channels)?);
+                }
+                if let Some(frames) = args.opt_value_from_str("--quantize")? {
+                    config = config.with_quantize(frames);
+                }
+                if args.contains("--staccato") {
+                    config = config.staccato();
+                }
+                if let Some(address) = args.opt_value_from_fn("--address", parse_address)? {
+                    config = config.with_address(address);
+                }
+                if let Some(format) = args.opt_value_from_str::<_, String>("--format")? {
+                    config = config.with_format(match format.as_str() {
+                        "bin" => uw8_tool::MidiOutputFormat::Binary,
+                        "cwa" => uw8_tool::MidiOutputFormat::Cwa,
+                        "wat" => uw8_tool::MidiOutputFormat::Wat,
+                        _ => bail!("Unknown output format '{}'", format),
+                    });
+                }
+                let source: PathBuf = args.free_from_str()?;
+                let dest: PathBuf = args.free_from_str()?;
+                let stats = uw8_tool::convert_midi_file(&source, &dest, &config)?;
+                println!(
+                    "{} events over {} frames ({:.1}s), {} bytes",
+                    stats.events,
+                    stats.frames,
+                    stats.frames as f32 / 60.,
+                    stats.bytes
+                );
+            }
             _ => {
                 eprintln!("Unknown subcommand '{}'", cmd);
                 print_help();
@@ -58,6 +91,36 @@ fn print_help() {
     uw8-tool make-base <version>
     uw8-tool pack <wasm file> <uw8 file>
     uw8-tool unpack <uw8 file> <wasm file>
-    uw8-tool filter-exports <wasm file> <wasm file>"
+    uw8-tool filter-exports <wasm file> <wasm file>
+    uw8-tool midi [<options>] <midi file> <bin/cwa/wat file>
+
+Options for midi:
+    --channels A,B,C,D : MIDI channels (1-16) played on the sndGes channels 0-3, defaults to 1,2,3,4
+    --quantize FRAMES  : Round all events to a multiple of FRAMES frames
+    --staccato         : Play all notes as note+128 and drop the note offs
+    --address ADDR     : Address of the data block in cwa/wat output, defaults to 0x20000
+    --format FORMAT    : bin, cwa or wat, defaults to the extension of the output file"
     );
 }
+
+fn parse_midi_channels(s: &str) -> Result<[u8; 4]> {
+    let channels = s
+        .split(',')
+        .map(|c| match c.trim().parse::<u8>() {
+            Ok(channel @ 1..=16) => Ok(channel - 1),
+            _ => Err(anyhow!("Invalid MIDI channel '{}'", c)),
+        })
+        .collect::<Result<Vec<u8>>>()?;
+    channels
+        .try_into()
+        .map_err(|_| anyhow!("Expected four MIDI channels, got '{}'", s))
+}
+
+fn parse_address(s: &str) -> Result<u32> {
+    let address = if let Some(hex) = s.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else {
+        s.parse()
+    };
+    address.map_err(|_| anyhow!("Invalid address '{}'", s))
+}
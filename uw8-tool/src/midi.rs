@@ -0,0 +1,397 @@
+use anyhow::{anyhow, bail, Result};
+use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use std::{fs::File, io::prelude::*, path::Path};
+
+/// Marks a wait of 63 frames without a note event, when found in the low 6 bits.
+const WAIT: u8 = 63;
+const END: u8 = 0xff;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MidiOutputFormat {
+    Binary,
+    Cwa,
+    Wat,
+}
+
+pub struct MidiConfig {
+    channels: [u8; 4],
+    quantize: u32,
+    staccato: bool,
+    address: u32,
+    format: Option<MidiOutputFormat>,
+}
+
+impl MidiConfig {
+    /// The MIDI channels (0-15) played on the `sndGes` channels 0-3.
+    pub fn with_channels(mut self, channels: [u8; 4]) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    /// Rounds all events to a multiple of `frames` frames. Fewer distinct
+    /// delta times compress better.
+    pub fn with_quantize(mut self, frames: u32) -> Self {
+        self.quantize = frames.max(1);
+        self
+    }
+
+    /// Plays every note as `note | 128` (attack + release) and drops all note offs,
+    /// halving the number of events for percussive parts.
+    pub fn staccato(mut self) -> Self {
+        self.staccato = true;
+        self
+    }
+
+    /// Address of the data block in the cwa and wat output.
+    pub fn with_address(mut self, address: u32) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// Output format, by default chosen by the extension of the output file.
+    pub fn with_format(mut self, format: MidiOutputFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+}
+
+impl Default for MidiConfig {
+    fn default() -> MidiConfig {
+        MidiConfig {
+            channels: [0, 1, 2, 3],
+            quantize: 1,
+            staccato: false,
+            address: 0x20000,
+            format: None,
+        }
+    }
+}
+
+/// Size of a converted event table.
+pub struct MidiStats {
+    pub events: usize,
+    pub frames: u32,
+    pub bytes: usize,
+}
+
+pub fn convert_midi_file(source: &Path, dest: &Path, config: &MidiConfig) -> Result<MidiStats> {
+    let mut source_data = vec![];
+    File::open(source)?.read_to_end(&mut source_data)?;
+
+    let (table, stats) = convert_midi(&source_data, config)?;
+
+    let format = config
+        .format
+        .unwrap_or_else(|| match dest.extension().and_then(|e| e.to_str()) {
+            Some("cwa") => MidiOutputFormat::Cwa,
+            Some("wat") => MidiOutputFormat::Wat,
+            _ => MidiOutputFormat::Binary,
+        });
+    let name = source
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut file = File::create(dest)?;
+    match format {
+        MidiOutputFormat::Binary => file.write_all(&table)?,
+        MidiOutputFormat::Cwa => {
+            writeln!(
+                file,
+                "// sndGes event table converted from {}, {} events in {} bytes",
+                name, stats.events, stats.bytes
+            )?;
+            writeln!(file, "data 0x{:x} {{", config.address)?;
+            for line in table.chunks(16) {
+                let bytes: Vec<String> = line.iter().map(|b| format!("0x{:02x}", b)).collect();
+                writeln!(file, "    i8({})", bytes.join(", "))?;
+            }
+            writeln!(file, "}}")?;
+        }
+        MidiOutputFormat::Wat => {
+            writeln!(
+                file,
+                ";; sndGes event table converted from {}, {} events in {} bytes",
+                name, stats.events, stats.bytes
+            )?;
+            write!(file, "(data (i32.const {}) \"", config.address)?;
+            for byte in &table {
+                write!(file, "\\{:02x}", byte)?;
+            }
+            writeln!(file, "\")")?;
+        }
+    }
+    Ok(stats)
+}
+
+/// Converts a standard MIDI file into a table of `playNote` calls per frame.
+///
+/// Each event is a byte `cc dddddd` followed by the note argument of `playNote`
+/// for channel `c`, `d` frames (0-62) after the previous event. A first byte with
+/// `d` = 63 waits 63 frames without a note byte following, 0xff ends the table.
+///
+/// The channels are monophonic: a note on replaces the current note of the channel
+/// and a note off only stops the note it belongs to. When a note starts and stops
+/// within the same frame it is played as `note | 128`.
+pub fn convert_midi(data: &[u8], config: &MidiConfig) -> Result<(Vec<u8>, MidiStats)> {
+    let smf = Smf::parse(data).map_err(|err| anyhow!("Failed to parse MIDI file: {}", err))?;
+
+    let mut tempo_changes: Vec<(u64, u32)> = vec![];
+    // (tick, track, index in track, sndGes channel, key, note on)
+    let mut notes: Vec<(u64, usize, usize, usize, u8, bool)> = vec![];
+    for (track_index, track) in smf.tracks.iter().enumerate() {
+        let mut tick = 0u64;
+        for (index, event) in track.iter().enumerate() {
+            tick += event.delta.as_int() as u64;
+            match event.kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => {
+                    tempo_changes.push((tick, tempo.as_int()))
+                }
+                TrackEventKind::Midi { channel, message } => {
+                    let channel = match config.channels.iter().position(|&c| c == channel.as_int())
+                    {
+                        Some(channel) => channel,
+                        None => continue,
+                    };
+                    match message {
+                        MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
+                            notes.push((tick, track_index, index, channel, key.as_int(), true))
+                        }
+                        MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                            notes.push((tick, track_index, index, channel, key.as_int(), false))
+                        }
+                        _ => (),
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    tempo_changes.sort_by_key(|&(tick, _)| tick);
+    // note offs before note ons on the same tick, so that repeated notes are retriggered
+    notes.sort_by_key(|&(tick, track, index, _, _, on)| (tick, on, track, index));
+
+    let tick_to_frame = |tick: u64| -> u32 {
+        let seconds = match smf.header.timing {
+            Timing::Metrical(ticks_per_beat) => {
+                let ticks_per_beat = ticks_per_beat.as_int().max(1) as f64;
+                let mut seconds = 0.0;
+                let mut last_tick = 0;
+                let mut tempo = 500000;
+                for &(change_tick, new_tempo) in &tempo_changes {
+                    if change_tick >= tick {
+                        break;
+                    }
+                    seconds += (change_tick - last_tick) as f64 * tempo as f64
+                        / ticks_per_beat
+                        / 1_000_000.0;
+                    last_tick = change_tick;
+                    tempo = new_tempo;
+                }
+                seconds + (tick - last_tick) as f64 * tempo as f64 / ticks_per_beat / 1_000_000.0
+            }
+            Timing::Timecode(fps, subframes) => {
+                tick as f64 / (fps.as_f32() as f64 * subframes.max(1) as f64)
+            }
+        };
+        let frame = (seconds * 60.0).round() as u32;
+        (frame + config.quantize / 2) / config.quantize * config.quantize
+    };
+
+    // (frame, channel, playNote argument)
+    let mut events: Vec<(u32, usize, u8)> = vec![];
+    let mut held: [Option<u8>; 4] = [None; 4];
+    for &(tick, _, _, channel, key, on) in &notes {
+        // note 0 would stop the channel
+        if key == 0 {
+            continue;
+        }
+        let value = if on {
+            held[channel] = Some(key);
+            if config.staccato {
+                key | 128
+            } else {
+                key
+            }
+        } else if held[channel] == Some(key) {
+            held[channel] = None;
+            if config.staccato {
+                continue;
+            }
+            0
+        } else {
+            continue;
+        };
+
+        let frame = tick_to_frame(tick);
+        let previous = events
+            .iter_mut()
+            .rev()
+            .find(|&&mut (_, c, _)| c == channel)
+            .filter(|&&mut (f, _, _)| f == frame);
+        match previous {
+            // note on and off in the same frame
+            Some(previous) if value == 0 => {
+                if previous.2 != 0 {
+                    previous.2 |= 128;
+                }
+            }
+            Some(previous) => previous.2 = value,
+            None => events.push((frame, channel, value)),
+        }
+    }
+    if events.is_empty() {
+        bail!(
+            "No notes found on MIDI channels {:?}",
+            config.channels.map(|c| c + 1)
+        );
+    }
+    events.sort_by_key(|&(frame, channel, _)| (frame, channel));
+
+    let mut table = vec![];
+    let mut last_frame = 0;
+    for &(frame, channel, value) in &events {
+        let mut delta = frame - last_frame;
+        while delta >= WAIT as u32 {
+            table.push(WAIT);
+            delta -= WAIT as u32;
+        }
+        table.push(((channel as u8) << 6) | delta as u8);
+        table.push(value);
+        last_frame = frame;
+    }
+    table.push(END);
+
+    let stats = MidiStats {
+        events: events.len(),
+        frames: last_frame,
+        bytes: table.len(),
+    };
+    Ok((table, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::num::{u15, u28, u4, u7};
+    use midly::{Format, Header, TrackEvent};
+
+    /// A single track with `ticks_per_frame` ticks per frame at the default tempo of 120bpm.
+    /// The notes are (tick, MIDI channel, key, note on), in order.
+    fn midi_file(ticks_per_frame: u16, notes: &[(u32, u8, u8, bool)]) -> Vec<u8> {
+        let mut track = vec![];
+        let mut last_tick = 0;
+        for &(tick, channel, key, on) in notes {
+            let message = if on {
+                MidiMessage::NoteOn {
+                    key: u7::new(key),
+                    vel: u7::new(100),
+                }
+            } else {
+                MidiMessage::NoteOff {
+                    key: u7::new(key),
+                    vel: u7::new(0),
+                }
+            };
+            track.push(TrackEvent {
+                delta: u28::new(tick - last_tick),
+                kind: TrackEventKind::Midi {
+                    channel: u4::new(channel),
+                    message,
+                },
+            });
+            last_tick = tick;
+        }
+        track.push(TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+
+        let smf = Smf {
+            header: Header::new(
+                Format::SingleTrack,
+                Timing::Metrical(u15::new(30 * ticks_per_frame)),
+            ),
+            tracks: vec![track],
+        };
+        let mut data = vec![];
+        smf.write_std(&mut data).unwrap();
+        data
+    }
+
+    /// Converts notes given in frames.
+    fn convert(notes: &[(u32, u8, u8, bool)], config: &MidiConfig) -> Vec<u8> {
+        convert_midi(&midi_file(1, notes), config).unwrap().0
+    }
+
+    #[test]
+    fn note_on_and_off() {
+        let table = convert(
+            &[(0, 0, 60, true), (10, 0, 60, false), (12, 1, 62, true)],
+            &MidiConfig::default(),
+        );
+        assert_eq!(table, [0x00, 60, 0x0a, 0, 0x42, 62, END]);
+    }
+
+    #[test]
+    fn note_off_in_the_same_frame_merges() {
+        // a quarter frame apart
+        let data = midi_file(4, &[(0, 0, 60, true), (1, 0, 60, false), (20, 0, 62, true)]);
+        let (table, stats) = convert_midi(&data, &MidiConfig::default()).unwrap();
+        assert_eq!(table, [0x00, 60 | 128, 0x05, 62, END]);
+        assert_eq!(stats.events, 2);
+        assert_eq!(stats.frames, 5);
+        assert_eq!(stats.bytes, 5);
+    }
+
+    #[test]
+    fn repeated_note_is_retriggered() {
+        // the note off on the same tick is sorted before the new note on
+        let table = convert(
+            &[(0, 0, 60, true), (10, 0, 60, true), (10, 0, 60, false)],
+            &MidiConfig::default(),
+        );
+        assert_eq!(table, [0x00, 60, 0x0a, 60, END]);
+    }
+
+    #[test]
+    fn long_waits() {
+        let table = convert(
+            &[(0, 0, 60, true), (100, 1, 64, true)],
+            &MidiConfig::default(),
+        );
+        assert_eq!(table, [0x00, 60, WAIT, 0x40 | 37, 64, END]);
+    }
+
+    #[test]
+    fn quantize() {
+        let table = convert(
+            &[(5, 0, 60, true), (7, 0, 60, false), (13, 0, 62, true)],
+            &MidiConfig::default().with_quantize(4),
+        );
+        assert_eq!(table, [0x04, 60, 0x04, 0, 0x04, 62, END]);
+    }
+
+    #[test]
+    fn staccato() {
+        let table = convert(
+            &[
+                (0, 0, 60, true),
+                (5, 0, 60, false),
+                (10, 0, 62, true),
+                (20, 0, 62, false),
+            ],
+            &MidiConfig::default().staccato(),
+        );
+        assert_eq!(table, [0x00, 60 | 128, 0x0a, 62 | 128, END]);
+    }
+
+    #[test]
+    fn channel_selection() {
+        let table = convert(
+            &[(0, 9, 36, true), (1, 2, 60, true)],
+            &MidiConfig::default().with_channels([9, 15, 15, 15]),
+        );
+        assert_eq!(table, [0x00, 36, END]);
+    }
+}
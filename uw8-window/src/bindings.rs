@@ -0,0 +1,352 @@
+use crate::Input;
+use anyhow::{anyhow, bail, Result};
+use crossterm::event::KeyCode;
+use std::path::Path;
+use winit::event::VirtualKeyCode;
+
+macro_rules! keys {
+    (
+        same: [$($name:ident)*],
+        renamed: [$($renamed:ident: $minifb:ident, $winit:ident;)*]
+    ) => {
+        /// Keys that can be bound, independent of the window backend.
+        #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+        pub(crate) enum Key {
+            $($name,)*
+            $($renamed,)*
+        }
+
+        impl Key {
+            fn from_name(name: &str) -> Option<Key> {
+                $(
+                    if name.eq_ignore_ascii_case(stringify!($name)) {
+                        return Some(Key::$name);
+                    }
+                )*
+                $(
+                    if name.eq_ignore_ascii_case(stringify!($renamed)) {
+                        return Some(Key::$renamed);
+                    }
+                )*
+                None
+            }
+
+            pub(crate) fn from_minifb(key: minifb::Key) -> Option<Key> {
+                match key {
+                    $(minifb::Key::$name => Some(Key::$name),)*
+                    $(minifb::Key::$minifb => Some(Key::$renamed),)*
+                    _ => None,
+                }
+            }
+
+            pub(crate) fn from_winit(key: VirtualKeyCode) -> Option<Key> {
+                match key {
+                    $(VirtualKeyCode::$name => Some(Key::$name),)*
+                    $(VirtualKeyCode::$winit => Some(Key::$renamed),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+keys! {
+    same: [
+        A B C D E F G H I J K L M N O P Q R S T U V W X Y Z
+        Key0 Key1 Key2 Key3 Key4 Key5 Key6 Key7 Key8 Key9
+        F1 F2 F3 F4 F5 F6 F7 F8 F9 F10 F11 F12
+        Up Down Left Right
+        Space Tab Escape Comma Period Slash Semicolon Minus Apostrophe Backslash
+        Insert Delete Home End PageUp PageDown Pause
+    ],
+    renamed: [
+        Backspace: Backspace, Back;
+        Enter: Enter, Return;
+        Equal: Equal, Equals;
+        LBracket: LeftBracket, LBracket;
+        RBracket: RightBracket, RBracket;
+        LShift: LeftShift, LShift;
+        RShift: RightShift, RShift;
+        LCtrl: LeftCtrl, LControl;
+        RCtrl: RightCtrl, RControl;
+        LAlt: LeftAlt, LAlt;
+        RAlt: RightAlt, RAlt;
+        Numpad0: NumPad0, Numpad0;
+        Numpad1: NumPad1, Numpad1;
+        Numpad2: NumPad2, Numpad2;
+        Numpad3: NumPad3, Numpad3;
+        Numpad4: NumPad4, Numpad4;
+        Numpad5: NumPad5, Numpad5;
+        Numpad6: NumPad6, Numpad6;
+        Numpad7: NumPad7, Numpad7;
+        Numpad8: NumPad8, Numpad8;
+        Numpad9: NumPad9, Numpad9;
+        NumpadAdd: NumPadPlus, NumpadAdd;
+        NumpadSubtract: NumPadMinus, NumpadSubtract;
+        NumpadEnter: NumPadEnter, NumpadEnter;
+    ]
+}
+
+impl Key {
+    /// Terminals only report the character typed for most keys, so the numpad and
+    /// the modifier keys can't be told apart.
+    pub(crate) fn from_crossterm(code: KeyCode) -> Option<Key> {
+        const F_KEYS: [Key; 12] = [
+            Key::F1,
+            Key::F2,
+            Key::F3,
+            Key::F4,
+            Key::F5,
+            Key::F6,
+            Key::F7,
+            Key::F8,
+            Key::F9,
+            Key::F10,
+            Key::F11,
+            Key::F12,
+        ];
+        match code {
+            KeyCode::Char(c) if c.is_ascii_alphabetic() => Key::from_name(&c.to_string()),
+            KeyCode::Char(c) if c.is_ascii_digit() => Key::from_name(&format!("Key{}", c)),
+            KeyCode::Char(' ') => Some(Key::Space),
+            KeyCode::Char(',') => Some(Key::Comma),
+            KeyCode::Char('.') => Some(Key::Period),
+            KeyCode::Char('/') => Some(Key::Slash),
+            KeyCode::Char(';') => Some(Key::Semicolon),
+            KeyCode::Char('-') => Some(Key::Minus),
+            KeyCode::Char('\'') => Some(Key::Apostrophe),
+            KeyCode::Char('\\') => Some(Key::Backslash),
+            KeyCode::Char('=') => Some(Key::Equal),
+            KeyCode::Char('[') => Some(Key::LBracket),
+            KeyCode::Char(']') => Some(Key::RBracket),
+            KeyCode::F(n) => F_KEYS.get((n as usize).wrapping_sub(1)).copied(),
+            KeyCode::Up => Some(Key::Up),
+            KeyCode::Down => Some(Key::Down),
+            KeyCode::Left => Some(Key::Left),
+            KeyCode::Right => Some(Key::Right),
+            KeyCode::Tab => Some(Key::Tab),
+            KeyCode::Esc => Some(Key::Escape),
+            KeyCode::Backspace => Some(Key::Backspace),
+            KeyCode::Enter => Some(Key::Enter),
+            KeyCode::Insert => Some(Key::Insert),
+            KeyCode::Delete => Some(Key::Delete),
+            KeyCode::Home => Some(Key::Home),
+            KeyCode::End => Some(Key::End),
+            KeyCode::PageUp => Some(Key::PageUp),
+            KeyCode::PageDown => Some(Key::PageDown),
+            KeyCode::Pause => Some(Key::Pause),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Action {
+    /// player (0-3) and bit (0-7) of a gamepad button
+    Button(u8, u8),
+    Reset,
+    Fullscreen,
+    Filter(u32),
+    /// load state slot (1-4), save when shift is held
+    StateSlot(u8),
+    Rewind,
+    Pause,
+    Step,
+    Slower,
+    Faster,
+    Quit,
+    /// toggle muting a sndGes channel (0-3)
+    Mute(u8),
+    /// toggle playing only the solo channels (0-3)
+    Solo(u8),
+}
+
+// in the order of the gamepad bits
+static BUTTON_NAMES: &[&str] = &["up", "down", "left", "right", "a", "b", "x", "y"];
+
+impl Action {
+    fn from_name(name: &str) -> Option<Action> {
+        fn index(name: &str, prefix: &str, range: std::ops::RangeInclusive<u32>) -> Option<u32> {
+            name.strip_prefix(prefix)?
+                .parse()
+                .ok()
+                .filter(|i| range.contains(i))
+        }
+
+        if let Some((player, button)) = name.split_once('.') {
+            let player = index(player, "p", 1..=4)?;
+            let bit = BUTTON_NAMES.iter().position(|&b| b == button)?;
+            return Some(Action::Button(player as u8 - 1, bit as u8));
+        }
+        match name {
+            "reset" => Some(Action::Reset),
+            "fullscreen" => Some(Action::Fullscreen),
+            "rewind" => Some(Action::Rewind),
+            "pause" => Some(Action::Pause),
+            "step" => Some(Action::Step),
+            "slower" => Some(Action::Slower),
+            "faster" => Some(Action::Faster),
+            "quit" => Some(Action::Quit),
+            _ => index(name, "filter", 1..=5)
+                .map(Action::Filter)
+                .or_else(|| index(name, "state", 1..=4).map(|slot| Action::StateSlot(slot as u8)))
+                .or_else(|| index(name, "mute", 0..=3).map(|ch| Action::Mute(ch as u8)))
+                .or_else(|| index(name, "solo", 0..=3).map(|ch| Action::Solo(ch as u8))),
+        }
+    }
+}
+
+/// The mapping of keys to gamepad buttons and hotkeys, shared by all window backends.
+#[derive(Clone, Debug)]
+pub(crate) struct KeyBindings {
+    bindings: Vec<(Action, Key)>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> KeyBindings {
+        use Action::*;
+        let mut bindings = vec![];
+        for (bit, key) in [
+            Key::Up,
+            Key::Down,
+            Key::Left,
+            Key::Right,
+            Key::Z,
+            Key::X,
+            Key::A,
+            Key::S,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            bindings.push((Button(0, bit as u8), key));
+        }
+        bindings.extend([
+            (Reset, Key::R),
+            (Fullscreen, Key::F),
+            (Filter(1), Key::Key1),
+            (Filter(2), Key::Key2),
+            (Filter(3), Key::Key3),
+            (Filter(4), Key::Key4),
+            (Filter(5), Key::Key5),
+            (StateSlot(1), Key::F1),
+            (StateSlot(2), Key::F2),
+            (StateSlot(3), Key::F3),
+            (StateSlot(4), Key::F4),
+            (Rewind, Key::Backspace),
+            (Pause, Key::P),
+            (Step, Key::Period),
+            (Slower, Key::Minus),
+            (Faster, Key::Equal),
+            (Quit, Key::Escape),
+            (Mute(0), Key::F5),
+            (Mute(1), Key::F6),
+            (Mute(2), Key::F7),
+            (Mute(3), Key::F8),
+            (Solo(0), Key::F9),
+            (Solo(1), Key::F10),
+            (Solo(2), Key::F11),
+            (Solo(3), Key::F12),
+        ]);
+        KeyBindings { bindings }
+    }
+}
+
+impl KeyBindings {
+    /// Loads bindings from a file with lines of the form `action = key, key, ...`.
+    /// Actions not mentioned in the file keep their default keys.
+    pub(crate) fn load(path: &Path) -> Result<KeyBindings> {
+        let mut bindings = KeyBindings::default();
+        let content = std::fs::read_to_string(path)?;
+        for (line_number, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            bindings
+                .parse_line(line)
+                .map_err(|err| anyhow!("{}:{}: {}", path.display(), line_number + 1, err))?;
+        }
+        Ok(bindings)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<()> {
+        let (action_name, keys) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected 'action = key'"))?;
+        let action_name = action_name.trim();
+        let action = match Action::from_name(action_name) {
+            Some(action) => action,
+            None => bail!("Unknown action '{}'", action_name),
+        };
+        self.bindings.retain(|&(a, _)| a != action);
+        for key_name in keys.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+            match Key::from_name(key_name) {
+                Some(key) => self.bindings.push((action, key)),
+                None => bail!("Unknown key '{}'", key_name),
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn actions(&self, key: Key) -> impl Iterator<Item = Action> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |&&(_, k)| k == key)
+            .map(|&(action, _)| action)
+    }
+
+    /// Maps the keys of one frame to the `Input`, the same way for all window backends.
+    ///
+    /// `held` are all keys currently down, `pressed` the keys pressed since the last frame
+    /// (without key repeats), each with the state of the shift key at the time.
+    pub(crate) fn map_input(
+        &self,
+        held: &[Key],
+        pressed: &[(Key, bool)],
+    ) -> (Input, WindowHotkeys) {
+        let mut input = Input::default();
+        let mut hotkeys = WindowHotkeys::default();
+
+        for &key in held {
+            for action in self.actions(key) {
+                match action {
+                    Action::Button(player, bit) => input.gamepads[player as usize] |= 1 << bit,
+                    Action::Rewind => input.rewind = true,
+                    _ => (),
+                }
+            }
+        }
+
+        for &(key, shift) in pressed {
+            for action in self.actions(key) {
+                match action {
+                    Action::Button(..) | Action::Rewind => (),
+                    Action::Reset => input.reset = true,
+                    Action::StateSlot(slot) if shift => input.save_state = Some(slot),
+                    Action::StateSlot(slot) => input.load_state = Some(slot),
+                    Action::Pause => input.pause = true,
+                    Action::Step => input.step = true,
+                    Action::Slower => input.slower = true,
+                    Action::Faster => input.faster = true,
+                    Action::Mute(channel) => input.mute = Some(channel),
+                    Action::Solo(channel) => input.solo = Some(channel),
+                    Action::Quit => hotkeys.quit = true,
+                    Action::Fullscreen => hotkeys.fullscreen = true,
+                    Action::Filter(filter) => hotkeys.filter = Some(filter),
+                }
+            }
+        }
+
+        (input, hotkeys)
+    }
+}
+
+/// The hotkeys that act on the window itself rather than on the running cart.
+/// Backends without fullscreen or upscale filters ignore those.
+#[derive(Default)]
+pub(crate) struct WindowHotkeys {
+    pub(crate) quit: bool,
+    pub(crate) fullscreen: bool,
+    pub(crate) filter: Option<u32>,
+}
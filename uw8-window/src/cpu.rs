@@ -1,27 +1,19 @@
 use std::time::Instant;
 
+use crate::bindings::{Key, KeyBindings};
 use crate::{Input, WindowImpl};
 use anyhow::Result;
-use minifb::{Key, WindowOptions};
-
-static GAMEPAD_KEYS: &[Key] = &[
-    Key::Up,
-    Key::Down,
-    Key::Left,
-    Key::Right,
-    Key::Z,
-    Key::X,
-    Key::A,
-    Key::S,
-];
+use minifb::{KeyRepeat, WindowOptions};
 
 pub struct Window {
     window: minifb::Window,
     buffer: Vec<u32>,
+    bindings: KeyBindings,
+    is_open: bool,
 }
 
 impl Window {
-    pub fn new() -> Result<Window> {
+    pub fn new(bindings: KeyBindings) -> Result<Window> {
         #[cfg(target_os = "windows")]
         unsafe {
             winapi::um::timeapi::timeBeginPeriod(1);
@@ -37,28 +29,39 @@ impl Window {
         };
         let window = minifb::Window::new("MicroW8", 320, 240, options).unwrap();
 
-        Ok(Window { window, buffer })
+        Ok(Window {
+            window,
+            buffer,
+            bindings,
+            is_open: true,
+        })
     }
 }
 
 impl WindowImpl for Window {
     fn begin_frame(&mut self) -> Input {
-        let mut gamepads = [0u8; 4];
-        for key in self.window.get_keys() {
-            if let Some(index) = GAMEPAD_KEYS
-                .iter()
-                .enumerate()
-                .find(|(_, &k)| k == key)
-                .map(|(i, _)| i)
-            {
-                gamepads[0] |= 1 << index;
-            }
-        }
+        let held: Vec<Key> = self
+            .window
+            .get_keys()
+            .into_iter()
+            .filter_map(Key::from_minifb)
+            .collect();
+        let shift = self.window.is_key_down(minifb::Key::LeftShift)
+            || self.window.is_key_down(minifb::Key::RightShift);
+        let pressed: Vec<(Key, bool)> = self
+            .window
+            .get_keys_pressed(KeyRepeat::No)
+            .into_iter()
+            .filter_map(Key::from_minifb)
+            .map(|key| (key, shift))
+            .collect();
 
-        Input {
-            gamepads,
-            reset: self.window.is_key_pressed(Key::R, minifb::KeyRepeat::No),
+        // the cpu window supports neither fullscreen nor upscale filters
+        let (input, hotkeys) = self.bindings.map_input(&held, &pressed);
+        if hotkeys.quit {
+            self.is_open = false;
         }
+        input
     }
 
     fn end_frame(&mut self, framebuffer: &[u8], palette: &[u8], next_frame: Instant) {
@@ -78,6 +81,6 @@ impl WindowImpl for Window {
     }
 
     fn is_open(&self) -> bool {
-        self.window.is_open() && !self.window.is_key_down(Key::Escape)
+        self.is_open && self.window.is_open()
     }
 }
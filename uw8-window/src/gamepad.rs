@@ -0,0 +1,67 @@
+use gilrs::{Axis, Button, Gilrs};
+
+// same order as the gamepad bits in MicroW8 memory
+static BUTTONS: &[Button] = &[
+    Button::DPadUp,
+    Button::DPadDown,
+    Button::DPadLeft,
+    Button::DPadRight,
+    Button::South,
+    Button::East,
+    Button::West,
+    Button::North,
+];
+
+pub struct Gamepads {
+    gilrs: Option<Gilrs>,
+}
+
+impl Gamepads {
+    pub fn new() -> Gamepads {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(err) => {
+                eprintln!("Failed to initialize gamepad support: {}", err);
+                None
+            }
+        };
+        Gamepads { gilrs }
+    }
+
+    /// Adds the state of up to four connected controllers to the gamepad bytes.
+    pub fn update(&mut self, gamepads: &mut [u8; 4]) {
+        let gilrs = match self.gilrs {
+            Some(ref mut gilrs) => gilrs,
+            None => return,
+        };
+
+        // the gamepad state is only updated while processing events
+        while gilrs.next_event().is_some() {}
+
+        for (index, (_, gamepad)) in gilrs.gamepads().take(4).enumerate() {
+            let mut state = 0u8;
+            for (bit, &button) in BUTTONS.iter().enumerate() {
+                if gamepad.is_pressed(button) {
+                    state |= 1 << bit;
+                }
+            }
+
+            let x = gamepad.value(Axis::LeftStickX);
+            let y = gamepad.value(Axis::LeftStickY);
+            if y > 0.5 {
+                state |= 1;
+            }
+            if y < -0.5 {
+                state |= 2;
+            }
+            if x < -0.5 {
+                state |= 4;
+            }
+            if x > 0.5 {
+                state |= 8;
+            }
+
+            gamepads[index] |= state;
+        }
+    }
+}
@@ -1,10 +1,11 @@
+use crate::bindings::{Key, KeyBindings};
 use crate::{Input, WindowConfig, WindowImpl};
 use anyhow::{anyhow, Result};
 use std::{num::NonZeroU32, time::Instant};
 
 use winit::{
     dpi::PhysicalSize,
-    event::{Event, VirtualKeyCode, WindowEvent},
+    event::{ElementState, Event, ModifiersState, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::{Fullscreen, WindowBuilder},
 };
@@ -30,7 +31,9 @@ pub struct Window {
     filter: Box<dyn Filter>,
     event_loop: EventLoop<()>,
     window: winit::window::Window,
-    gamepads: [u8; 4],
+    bindings: KeyBindings,
+    held_keys: Vec<Key>,
+    modifiers: ModifiersState,
     next_frame: Instant,
     is_fullscreen: bool,
     is_open: bool,
@@ -96,7 +99,9 @@ impl Window {
                 palette_screen_mode,
                 surface_config,
                 filter,
-                gamepads: [0; 4],
+                bindings: window_config.bindings,
+                held_keys: vec![],
+                modifiers: ModifiersState::empty(),
                 next_frame: Instant::now(),
                 is_fullscreen: window_config.fullscreen,
                 is_open: true,
@@ -109,10 +114,9 @@ impl Window {
 
 impl WindowImpl for Window {
     fn begin_frame(&mut self) -> Input {
-        let mut reset = false;
+        let mut pressed = vec![];
         self.event_loop.run_return(|event, _, control_flow| {
             *control_flow = ControlFlow::WaitUntil(self.next_frame);
-            let mut new_filter = None;
             match event {
                 Event::WindowEvent { event, .. } => match event {
                     WindowEvent::Resized(new_size) => {
@@ -125,49 +129,23 @@ impl WindowImpl for Window {
                         self.is_open = false;
                         *control_flow = ControlFlow::Exit;
                     }
-                    WindowEvent::KeyboardInput { input, .. } => {
-                        fn gamepad_button(input: &winit::event::KeyboardInput) -> u8 {
-                            match input.scancode {
-                                44 => 16,
-                                45 => 32,
-                                30 => 64,
-                                31 => 128,
-                                _ => match input.virtual_keycode {
-                                    Some(VirtualKeyCode::Up) => 1,
-                                    Some(VirtualKeyCode::Down) => 2,
-                                    Some(VirtualKeyCode::Left) => 4,
-                                    Some(VirtualKeyCode::Right) => 8,
-                                    _ => 0,
-                                },
+                    WindowEvent::ModifiersChanged(modifiers) => self.modifiers = modifiers,
+                    WindowEvent::KeyboardInput {
+                        input: keyboard_input,
+                        ..
+                    } => {
+                        let key = match keyboard_input.virtual_keycode.and_then(Key::from_winit) {
+                            Some(key) => key,
+                            None => return,
+                        };
+                        if keyboard_input.state == ElementState::Pressed {
+                            // ignore key repeats, hotkeys should trigger once per press
+                            if !self.held_keys.contains(&key) {
+                                self.held_keys.push(key);
+                                pressed.push((key, self.modifiers.shift()));
                             }
-                        }
-                        if input.state == winit::event::ElementState::Pressed {
-                            match input.virtual_keycode {
-                                Some(VirtualKeyCode::Escape) => {
-                                    self.is_open = false;
-                                    *control_flow = ControlFlow::Exit;
-                                }
-                                Some(VirtualKeyCode::F) => {
-                                    let fullscreen = if self.window.fullscreen().is_some() {
-                                        None
-                                    } else {
-                                        Some(Fullscreen::Borderless(None))
-                                    };
-                                    self.is_fullscreen = fullscreen.is_some();
-                                    self.window.set_fullscreen(fullscreen);
-                                }
-                                Some(VirtualKeyCode::R) => reset = true,
-                                Some(VirtualKeyCode::Key1) => new_filter = Some(1),
-                                Some(VirtualKeyCode::Key2) => new_filter = Some(2),
-                                Some(VirtualKeyCode::Key3) => new_filter = Some(3),
-                                Some(VirtualKeyCode::Key4) => new_filter = Some(4),
-                                Some(VirtualKeyCode::Key5) => new_filter = Some(5),
-                                _ => (),
-                            }
-
-                            self.gamepads[0] |= gamepad_button(&input);
                         } else {
-                            self.gamepads[0] &= !gamepad_button(&input);
+                            self.held_keys.retain(|&k| k != key);
                         }
                     }
                     _ => (),
@@ -182,20 +160,31 @@ impl WindowImpl for Window {
                 }
                 _ => (),
             }
-            if let Some(new_filter) = new_filter {
-                self.filter = create_filter(
-                    &self.device,
-                    &self.palette_screen_mode.screen_view,
-                    self.window.inner_size(),
-                    self.surface_config.format,
-                    new_filter,
-                );
-            }
         });
-        Input {
-            gamepads: self.gamepads,
-            reset,
+
+        let (input, hotkeys) = self.bindings.map_input(&self.held_keys, &pressed);
+        if hotkeys.quit {
+            self.is_open = false;
+        }
+        if hotkeys.fullscreen {
+            let fullscreen = if self.window.fullscreen().is_some() {
+                None
+            } else {
+                Some(Fullscreen::Borderless(None))
+            };
+            self.is_fullscreen = fullscreen.is_some();
+            self.window.set_fullscreen(fullscreen);
+        }
+        if let Some(new_filter) = hotkeys.filter {
+            self.filter = create_filter(
+                &self.device,
+                &self.palette_screen_mode.screen_view,
+                self.window.inner_size(),
+                self.surface_config.format,
+                new_filter,
+            );
         }
+        input
     }
 
     fn end_frame(&mut self, framebuffer: &[u8], palette: &[u8], next_frame: Instant) {
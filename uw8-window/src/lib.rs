@@ -1,11 +1,19 @@
 use anyhow::Result;
+use std::path::PathBuf;
 use std::time::Instant;
 
+mod bindings;
 mod cpu;
+mod gamepad;
 mod gpu;
+mod terminal;
+
+use bindings::KeyBindings;
+use gamepad::Gamepads;
 
 pub struct Window {
     inner: Box<dyn WindowImpl>,
+    gamepads: Gamepads,
     fps_counter: Option<FpsCounter>,
 }
 
@@ -24,11 +32,20 @@ impl Window {
         } else {
             None
         };
+        let bindings = config.bindings.clone();
+        if config.terminal || !has_display() {
+            return terminal::Window::new(bindings).map(|window| Window {
+                inner: Box::new(window),
+                gamepads: Gamepads::new(),
+                fps_counter,
+            });
+        }
         if config.enable_gpu {
             match gpu::Window::new(config) {
                 Ok(window) => {
                     return Ok(Window {
                         inner: Box::new(window),
+                        gamepads: Gamepads::new(),
                         fps_counter,
                     })
                 }
@@ -38,14 +55,17 @@ impl Window {
                 ),
             }
         }
-        cpu::Window::new().map(|window| Window {
+        cpu::Window::new(bindings).map(|window| Window {
             inner: Box::new(window),
+            gamepads: Gamepads::new(),
             fps_counter,
         })
     }
 
     pub fn begin_frame(&mut self) -> Input {
-        self.inner.begin_frame()
+        let mut input = self.inner.begin_frame();
+        self.gamepads.update(&mut input.gamepads);
+        input
     }
     pub fn end_frame(&mut self, framebuffer: &[u8], palette: &[u8], next_frame: Instant) {
         self.inner.end_frame(framebuffer, palette, next_frame);
@@ -65,21 +85,34 @@ impl Window {
     }
 }
 
+// only X11 and Wayland need a display server to open a window
+fn has_display() -> bool {
+    if cfg!(all(unix, not(target_os = "macos"))) {
+        std::env::var_os("DISPLAY").is_some() || std::env::var_os("WAYLAND_DISPLAY").is_some()
+    } else {
+        true
+    }
+}
+
 #[derive(Debug)]
 pub struct WindowConfig {
     enable_gpu: bool,
+    terminal: bool,
     filter: u32,
     fullscreen: bool,
     fps_counter: bool,
+    bindings: KeyBindings,
 }
 
 impl Default for WindowConfig {
     fn default() -> WindowConfig {
         WindowConfig {
             enable_gpu: true,
+            terminal: false,
             filter: 5,
             fullscreen: false,
             fps_counter: false,
+            bindings: KeyBindings::default(),
         }
     }
 }
@@ -87,6 +120,7 @@ impl Default for WindowConfig {
 impl WindowConfig {
     pub fn parse_arguments(&mut self, args: &mut pico_args::Arguments) {
         self.enable_gpu = !args.contains("--no-gpu");
+        self.terminal = args.contains("--terminal");
         if let Some(filter) = args.opt_value_from_str::<_, String>("--filter").unwrap() {
             self.filter = match filter.as_str() {
                 "1" | "nearest" => 1,
@@ -102,12 +136,40 @@ impl WindowConfig {
         }
         self.fullscreen = args.contains("--fullscreen");
         self.fps_counter = args.contains("--fps");
+        if let Some(path) = args.opt_value_from_str::<_, PathBuf>("--bindings").unwrap() {
+            match KeyBindings::load(&path) {
+                Ok(bindings) => self.bindings = bindings,
+                Err(err) => {
+                    println!("Failed to load key bindings: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
     }
 }
 
+#[derive(Default)]
 pub struct Input {
     pub gamepads: [u8; 4],
     pub reset: bool,
+    /// save state slot (1-4) to save to, Shift+F1-F4 by default
+    pub save_state: Option<u8>,
+    /// save state slot (1-4) to load from, F1-F4 by default
+    pub load_state: Option<u8>,
+    /// play backwards while held, Backspace by default
+    pub rewind: bool,
+    /// toggle pause, P by default
+    pub pause: bool,
+    /// run a single frame while paused, Period by default
+    pub step: bool,
+    /// decrease the speed, Minus by default
+    pub slower: bool,
+    /// increase the speed, Equal by default
+    pub faster: bool,
+    /// sndGes channel (0-3) to mute or unmute, F5-F8 by default
+    pub mute: Option<u8>,
+    /// sndGes channel (0-3) to add to or remove from the solo channels, F9-F12 by default
+    pub solo: Option<u8>,
 }
 
 trait WindowImpl {
@@ -0,0 +1,214 @@
+use std::fmt::Write as _;
+use std::io::{self, Write as _};
+use std::time::{Duration, Instant};
+
+use crate::bindings::{Key, KeyBindings};
+use crate::{Input, WindowImpl};
+use anyhow::Result;
+use crossterm::event::{
+    self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::{cursor, execute, terminal};
+
+// Most terminals only report key presses and their repeats. Without a release event,
+// a key counts as held for a while after it was last reported, long enough to bridge
+// the delay before the key repeat starts.
+const HOLD_TIME: Duration = Duration::from_millis(300);
+const REPEAT_HOLD_TIME: Duration = Duration::from_millis(100);
+
+// redraw an unchanged screen once in a while, in case other output messed it up
+const REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Window {
+    bindings: KeyBindings,
+    // keys currently held, with the time they are released if the terminal has no release events
+    held_keys: Vec<(Key, Option<Instant>)>,
+    release_events: bool,
+    enhanced_keyboard: bool,
+    screen: String,
+    last_screen: String,
+    last_size: (u16, u16),
+    last_draw: Instant,
+    is_open: bool,
+}
+
+impl Window {
+    pub fn new(bindings: KeyBindings) -> Result<Window> {
+        terminal::enable_raw_mode()?;
+        // from here on, dropping the window restores the terminal
+        let mut window = Window {
+            bindings,
+            held_keys: vec![],
+            // set by the first release event, eg. on windows
+            release_events: false,
+            enhanced_keyboard: false,
+            screen: String::new(),
+            last_screen: String::new(),
+            last_size: (0, 0),
+            last_draw: Instant::now(),
+            is_open: true,
+        };
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+
+        // terminals supporting the kitty keyboard protocol can report key releases
+        if terminal::supports_keyboard_enhancement().unwrap_or(false) {
+            execute!(
+                io::stdout(),
+                PushKeyboardEnhancementFlags(
+                    KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                        | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                )
+            )?;
+            window.enhanced_keyboard = true;
+            window.release_events = true;
+        }
+
+        Ok(window)
+    }
+
+    fn hold_key(&mut self, key: Key, hold_time: Duration) {
+        let release = if self.release_events {
+            None
+        } else {
+            Some(Instant::now() + hold_time)
+        };
+        match self.held_keys.iter_mut().find(|(k, _)| *k == key) {
+            Some(held) => held.1 = release,
+            None => self.held_keys.push((key, release)),
+        }
+    }
+
+    fn draw(&mut self, framebuffer: &[u8], palette: &[u8], columns: usize, rows: usize) {
+        // each character cell shows two pixels with the upper half block, keeping the 4:3 aspect ratio
+        let width = columns.min(rows * 2 * 4 / 3).max(1);
+        let height = (width * 3 / 4 / 2).max(1);
+        let left = (columns - width.min(columns)) / 2;
+        let top = (rows - height.min(rows)) / 2;
+
+        // the average color of the framebuffer area covered by the pixel at x, y
+        let pixel = |x: usize, y: usize| -> [u8; 3] {
+            let x0 = x * 320 / width;
+            let x1 = ((x + 1) * 320 / width).max(x0 + 1);
+            let y0 = y * 240 / (height * 2);
+            let y1 = ((y + 1) * 240 / (height * 2)).max(y0 + 1);
+            let mut sum = [0u32; 3];
+            for py in y0..y1 {
+                for &color_index in &framebuffer[py * 320 + x0..py * 320 + x1] {
+                    let color = &palette[color_index as usize * 4..][..3];
+                    for (s, &c) in sum.iter_mut().zip(color) {
+                        *s += c as u32;
+                    }
+                }
+            }
+            let count = ((x1 - x0) * (y1 - y0)) as u32;
+            sum.map(|s| (s / count) as u8)
+        };
+
+        self.screen.clear();
+        for row in 0..height {
+            let _ = write!(self.screen, "\x1b[{};{}H", top + row + 1, left + 1);
+            let mut last_colors = None;
+            for x in 0..width {
+                let colors = (pixel(x, row * 2), pixel(x, row * 2 + 1));
+                if last_colors != Some(colors) {
+                    let ([r, g, b], [br, bg, bb]) = colors;
+                    let _ = write!(
+                        self.screen,
+                        "\x1b[38;2;{};{};{};48;2;{};{};{}m",
+                        r, g, b, br, bg, bb
+                    );
+                    last_colors = Some(colors);
+                }
+                self.screen.push('\u{2580}');
+            }
+            self.screen.push_str("\x1b[0m");
+        }
+    }
+}
+
+impl WindowImpl for Window {
+    fn begin_frame(&mut self) -> Input {
+        let now = Instant::now();
+        self.held_keys
+            .retain(|&(_, release)| release.map(|time| time > now).unwrap_or(true));
+
+        let mut pressed = vec![];
+        while let Ok(true) = event::poll(Duration::ZERO) {
+            let event = match event::read() {
+                Ok(Event::Key(event)) => event,
+                Ok(_) => continue,
+                Err(_) => break,
+            };
+            // raw mode disables the interrupt signal
+            if event.code == KeyCode::Char('c') && event.modifiers.contains(KeyModifiers::CONTROL) {
+                self.is_open = false;
+            }
+            let key = match Key::from_crossterm(event.code) {
+                Some(key) => key,
+                None => continue,
+            };
+            match event.kind {
+                KeyEventKind::Press => {
+                    // without the kitty protocol, key repeats are reported as presses
+                    if !self.held_keys.iter().any(|&(k, _)| k == key) {
+                        pressed.push((key, event.modifiers.contains(KeyModifiers::SHIFT)));
+                    }
+                    self.hold_key(key, HOLD_TIME);
+                }
+                KeyEventKind::Repeat => self.hold_key(key, REPEAT_HOLD_TIME),
+                KeyEventKind::Release => {
+                    self.release_events = true;
+                    self.held_keys.retain(|&(k, _)| k != key);
+                }
+            }
+        }
+
+        let held: Vec<Key> = self.held_keys.iter().map(|&(key, _)| key).collect();
+        // the terminal supports neither fullscreen nor upscale filters
+        let (input, hotkeys) = self.bindings.map_input(&held, &pressed);
+        if hotkeys.quit {
+            self.is_open = false;
+        }
+        input
+    }
+
+    fn end_frame(&mut self, framebuffer: &[u8], palette: &[u8], next_frame: Instant) {
+        let size = terminal::size().unwrap_or((80, 24));
+        self.draw(framebuffer, palette, size.0 as usize, size.1 as usize);
+
+        if size != self.last_size
+            || self.screen != self.last_screen
+            || self.last_draw.elapsed() >= REDRAW_INTERVAL
+        {
+            let mut stdout = io::stdout().lock();
+            if size != self.last_size {
+                let _ = write!(stdout, "\x1b[2J");
+                self.last_size = size;
+            }
+            let _ = stdout.write_all(self.screen.as_bytes());
+            let _ = stdout.flush();
+            std::mem::swap(&mut self.screen, &mut self.last_screen);
+            self.last_draw = Instant::now();
+        }
+
+        if let Some(sleep) = next_frame.checked_duration_since(Instant::now()) {
+            std::thread::sleep(sleep);
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.is_open
+    }
+}
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        if self.enhanced_keyboard {
+            let _ = execute!(stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}